@@ -0,0 +1,698 @@
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
+
+use glutin::{Api, ContextBuilder, Event, EventsLoop, GlContext, GlProfile, GlRequest, GlWindow, WindowEvent};
+
+use simple_error::SimpleError;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ffi::CString;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::graphics::*;
+use crate::graphics::backends::save_screenshot;
+use crate::graphics::config::{Config, PresentMode};
+use crate::graphics::input::{winit_input, InputEvent, Key};
+use crate::graphics::texture::Texture;
+use crate::graphics::util::FrameLimiter;
+use crate::graphics::window::{winit_window, WindowOptions};
+
+winit_input!(glutin);
+winit_window!(glutin);
+
+/// Position, texture coordinate and colour, interleaved.
+const VERTEX_SIZE: usize = 9;
+
+/// Draws with OpenGL 3.3, for machines without Vulkan.
+///
+/// Colours are premultiplied and blended in linear space when the window supports sRGB, as with
+/// the Vulkan backend. Triangles are drawn in order, without a depth test.
+pub struct GlBackend {
+    show_fps: bool,
+    max_fps: Option<u32>,
+    /// Pressing the key saves the next frame as a PNG in the directory.
+    screenshot_key: Option<(Key, PathBuf)>,
+    program: Program,
+    /// Draws the offscreen image of a `Group`.
+    composite_program: Program,
+    vertex_array: GLuint,
+    vertex_buffer: GLuint,
+    index_buffer: GLuint,
+    textures: TextureCache,
+    /// Whether the framebuffer encodes to sRGB itself, so that colours are blended in linear space.
+    srgb: bool,
+    phys_dims: [u32; 2],
+    log_dims: [u32; 2],
+    /// What the window was last set up with.
+    window_options: WindowOptions,
+    events_loop: EventsLoop,
+    /// Fields are dropped in order, so this goes last to keep the context alive while the GL
+    /// objects above are deleted.
+    window: GlWindow,
+}
+
+impl GlBackend {
+    /// Renders every group in `data` (innermost first) into an offscreen layer of its own.
+    fn render_groups(&mut self, data: &RenderData, layers: &mut HashMap<*const Group, Layer>) -> Result<(), Box<dyn Error>> {
+        for group in data.batches.iter().filter_map(|batch| batch.group.as_ref()) {
+            self.render_groups(&group.data, layers)?;
+
+            let layer = Layer::new(self.phys_dims, self.texture_format())?;
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, layer.framebuffer);
+                gl::Disable(gl::SCISSOR_TEST);
+                gl::ClearColor(0., 0., 0., 0.);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+            }
+            self.draw(&group.data, layers);
+            layers.insert(Arc::as_ptr(group), layer);
+        }
+        Ok(())
+    }
+
+    /// Draws `data` into the bound framebuffer. Groups must already have been rendered into
+    /// `layers`.
+    fn draw(&mut self, data: &RenderData, layers: &HashMap<*const Group, Layer>) {
+        if data.indices.is_empty() {
+            return;
+        }
+
+        let mut vertices = Vec::with_capacity(data.vertices.len() * VERTEX_SIZE);
+        for ((vert, tex_coord), col) in data.vertices.iter().zip(data.tex_coords.iter()).zip(data.colours.iter()) {
+            vertices.extend_from_slice(&self.convert_vertex(*vert));
+            vertices.extend_from_slice(&[tex_coord.0, tex_coord.1]);
+            vertices.extend_from_slice(&self.convert_colour(*col));
+        }
+
+        unsafe {
+            gl::BindVertexArray(self.vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * mem::size_of::<f32>()) as isize,
+                           vertices.as_ptr() as *const _, gl::STREAM_DRAW);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (data.indices.len() * mem::size_of::<Index>()) as isize,
+                           data.indices.as_ptr() as *const _, gl::STREAM_DRAW);
+        }
+
+        for batch in data.batches.iter() {
+            // Multiply and overlay read what is below from the layer of the batch's backdrop.
+            let backdrop = match &batch.backdrop {
+                Some(backdrop) if batch.blend.needs_backdrop() => layers.get(&Arc::as_ptr(backdrop)),
+                _ => None,
+            };
+            let (program, texture, opacity) = match &batch.group {
+                Some(group) => match layers.get(&Arc::as_ptr(group)) {
+                    Some(layer) => (&self.composite_program, layer.texture, group.opacity),
+                    None => continue,
+                },
+                None => (&self.program, self.textures.get(batch.texture.as_ref()), 1.),
+            };
+
+            unsafe {
+                gl::UseProgram(program.id);
+                gl::Uniform1i(program.tex, 0);
+                gl::Uniform1i(program.backdrop, 1);
+                gl::Uniform1i(program.mode, if backdrop.is_some() { shader_mode(batch.blend) } else { 0 });
+                gl::Uniform1f(program.opacity, opacity);
+                if let Some(backdrop) = backdrop {
+                    gl::ActiveTexture(gl::TEXTURE1);
+                    gl::BindTexture(gl::TEXTURE_2D, backdrop.texture);
+                }
+                gl::ActiveTexture(gl::TEXTURE0);
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+
+                let (color_source, color_destination) = blend_factors(batch.blend);
+                gl::BlendFuncSeparate(color_source, color_destination, gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+
+                match batch.clip {
+                    Some(clip) => {
+                        let [x, y, width, height] = self.convert_clip(clip);
+                        gl::Enable(gl::SCISSOR_TEST);
+                        gl::Scissor(x, y, width, height);
+                    },
+                    None => gl::Disable(gl::SCISSOR_TEST),
+                }
+
+                gl::DrawElements(gl::TRIANGLES, batch.count as GLsizei, gl::UNSIGNED_INT,
+                                 (batch.start * mem::size_of::<Index>()) as *const _);
+            }
+        }
+    }
+
+    fn texture_format(&self) -> GLenum {
+        if self.srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 }
+    }
+
+    /// Converts to normalised device coordinates, where y points up.
+    fn convert_vertex(&self, vert: Vertex) -> [f32; 3] {
+        let (x, y, z) = match vert {
+            Vertex::Xy(x, y) => (x, y, 0.0),
+            Vertex::Xyz(x, y, z) => (x, y, z)
+        };
+        [(x / self.log_dims[0] as f32 - 0.5) * 2., (0.5 - y / self.log_dims[1] as f32) * 2., z]
+    }
+
+    /// Premultiplies a colour, first decoding it to linear if the framebuffer works in linear
+    /// space.
+    fn convert_colour(&self, col: Colour) -> [f32; 4] {
+        let col = if self.srgb { col.to_linear() } else { col };
+        let (r, g, b, a) = col.premultiplied().rgba();
+        [r, g, b, a]
+    }
+
+    /// Converts a clip rectangle in logical coordinates to a scissor box in physical pixels, as
+    /// (x, y, width, height) from the bottom-left.
+    fn convert_clip(&self, clip: Rect) -> [GLint; 4] {
+        let scale_x = self.phys_dims[0] as f32 / self.log_dims[0] as f32;
+        let scale_y = self.phys_dims[1] as f32 / self.log_dims[1] as f32;
+        let left = (clip.x * scale_x).round().max(0.);
+        let top = (clip.y * scale_y).round().max(0.);
+        let right = ((clip.x + clip.width) * scale_x).round().max(left);
+        let bottom = ((clip.y + clip.height) * scale_y).round().max(top);
+        [left as GLint, self.phys_dims[1] as GLint - bottom as GLint, (right - left) as GLint, (bottom - top) as GLint]
+    }
+
+    fn update_dims(&mut self) -> Result<(), Box<dyn Error>> {
+        let window = self.window.window();
+        let dimensions = window.get_inner_size().ok_or(SimpleError::new("Failed to load window dimensions"))?;
+        let physical = dimensions.to_physical(window.get_hidpi_factor());
+        let log: (u32, u32) = dimensions.into();
+        let phys: (u32, u32) = physical.into();
+        self.window.resize(physical);
+        self.log_dims = [log.0, log.1];
+        self.phys_dims = [phys.0, phys.1];
+        Ok(())
+    }
+
+    /// Saves what has been drawn into the back buffer as a timestamped PNG in `directory`.
+    fn save_screenshot(&self, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let [width, height] = self.phys_dims;
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl::ReadBuffer(gl::BACK);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, width as GLsizei, height as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE,
+                           pixels.as_mut_ptr() as *mut _);
+        }
+        // GL reads rows from the bottom up.
+        let pixels = pixels.chunks_exact(width as usize * 4).rev().flatten().copied().collect();
+        save_screenshot(pixels, width, height, self.srgb, directory)
+    }
+}
+
+impl GlBackend {
+    /// Opens a window, using the window options, present mode and multisampling in `config`.
+    /// OpenGL can only turn vsync on or off, so mailbox presentation draws without it.
+    pub fn with_config(config: &Config) -> Result<Self, Box<dyn Error>> {
+        println!("Beginning OpenGL setup...");
+        let events_loop = EventsLoop::new();
+        let window_builder = window_builder(&config.window, &events_loop);
+        let context_builder = ContextBuilder::new()
+            .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
+            .with_gl_profile(GlProfile::Core)
+            .with_srgb(true)
+            .with_vsync(matches!(config.present_mode, PresentMode::Fifo | PresentMode::FifoRelaxed))
+            .with_multisampling(if config.samples > 1 { config.samples.next_power_of_two() as u16 } else { 0 });
+        let window = GlWindow::new(window_builder, context_builder, &events_loop)?;
+
+        place_window(window.window(), &config.window);
+        unsafe { window.make_current()? };
+        gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
+        let srgb = window.get_pixel_format().srgb;
+        if !srgb {
+            println!("No sRGB framebuffer; blending in sRGB space.");
+        }
+
+        let program = Program::new(VERTEX_SHADER, FRAGMENT_SHADER)?;
+        let composite_program = Program::new(COMPOSITE_VERTEX_SHADER, COMPOSITE_FRAGMENT_SHADER)?;
+
+        let (mut vertex_array, mut vertex_buffer, mut index_buffer) = (0, 0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array);
+            gl::GenBuffers(1, &mut vertex_buffer);
+            gl::GenBuffers(1, &mut index_buffer);
+            gl::BindVertexArray(vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer);
+            let stride = (VERTEX_SIZE * mem::size_of::<f32>()) as GLsizei;
+            for &(location, size, offset) in [(0, 3, 0), (1, 2, 3), (2, 4, 5)].iter() {
+                gl::EnableVertexAttribArray(location);
+                gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride,
+                                        (offset * mem::size_of::<f32>()) as *const _);
+            }
+
+            gl::Enable(gl::BLEND);
+            if srgb {
+                gl::Enable(gl::FRAMEBUFFER_SRGB);
+            }
+        }
+
+        let textures = TextureCache::new(if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 });
+        let mut backend = Self {
+            show_fps: false,
+            max_fps: None,
+            screenshot_key: config.screenshot_key.clone(),
+            program,
+            composite_program,
+            vertex_array,
+            vertex_buffer,
+            index_buffer,
+            textures,
+            srgb,
+            phys_dims: [0, 0],
+            log_dims: [0, 0],
+            window_options: config.window.clone(),
+            events_loop,
+            window,
+        };
+        backend.update_dims()?;
+        Ok(backend)
+    }
+
+    /// While running, pressing `key` saves the frame as a timestamped PNG in `directory`.
+    pub fn screenshot_key<P: Into<PathBuf>>(mut self, key: Key, directory: P) -> Self {
+        self.screenshot_key = Some((key, directory.into()));
+        self
+    }
+}
+
+impl GfxProvider for GlBackend {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Self::with_config(&Config::new())
+    }
+
+    fn show_fps(mut self) -> Self {
+        self.show_fps = true;
+        self
+    }
+
+    fn max_fps(mut self, fps: Option<u32>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    fn run(mut self, mut vertex_producer: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>> {
+        let mut limiter = FrameLimiter::new(self.max_fps);
+        let mut t0 = Instant::now();
+        let mut updates = 0;
+        let fps_freq = 100;
+        let mut input = WinitInput::default();
+        let mut take_screenshot = false;
+        loop {
+            if self.show_fps {
+                updates += 1;
+                if updates % fps_freq == 0 {
+                    let ms = t0.elapsed().as_millis() as f32 / fps_freq as f32;
+                    println!("{} fps", 1000.0 / ms);
+                    t0 = Instant::now();
+                }
+            }
+
+            limiter.wait();
+            if let Some(options) = vertex_producer.window_options() {
+                update_window(self.window.window(), &self.window_options, &options);
+                self.window_options = options;
+            }
+
+            let data = vertex_producer.get_data(RuntimeParams {
+                window_width: self.log_dims[0] as u16,
+                window_height: self.log_dims[1] as u16
+            }).with_backdrops();
+
+            // Groups are drawn offscreen first, so they are ready before anything is drawn over.
+            let mut layers = HashMap::new();
+            unsafe { gl::Viewport(0, 0, self.phys_dims[0] as GLsizei, self.phys_dims[1] as GLsizei) };
+            self.render_groups(&data, &mut layers)?;
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl::Disable(gl::SCISSOR_TEST);
+                // A transparent window shows the desktop wherever nothing is drawn.
+                if self.window_options.transparent {
+                    gl::ClearColor(0., 0., 0., 0.);
+                } else {
+                    gl::ClearColor(1., 0., 1., 1.);
+                }
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+            }
+            self.draw(&data, &layers);
+            self.textures.end_frame();
+            if let (true, Some((_, directory))) = (take_screenshot, &self.screenshot_key) {
+                match self.save_screenshot(directory) {
+                    Ok(path) => println!("Saved screenshot to {}.", path.display()),
+                    Err(err) => println!("Failed to save screenshot: {}", err),
+                }
+            }
+            take_screenshot = false;
+            self.window.swap_buffers()?;
+
+            let mut done = false;
+            let mut resized = false;
+            let screenshot_key = self.screenshot_key.as_ref().map(|(key, _)| *key);
+            self.events_loop.poll_events(|ev| {
+                match ev {
+                    Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+                    Event::WindowEvent { event: WindowEvent::Resized(_), .. } => resized = true,
+                    Event::WindowEvent { event, .. } => if let Some(event) = input.convert(&event) {
+                        take_screenshot |= matches!(event, InputEvent::Key { key, .. } if Some(key) == screenshot_key);
+                        vertex_producer.handle_event(event);
+                    },
+                    _ => ()
+                }
+            });
+
+            if done {
+                return Ok(());
+            }
+            if resized {
+                self.update_dims()?;
+            }
+        }
+    }
+}
+
+impl Drop for GlBackend {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vertex_buffer);
+            gl::DeleteBuffers(1, &self.index_buffer);
+            gl::DeleteVertexArrays(1, &self.vertex_array);
+        }
+    }
+}
+
+/// The source and destination factors for colour, for premultiplied alpha. These match the
+/// Vulkan backend's.
+fn blend_factors(mode: BlendMode) -> (GLenum, GLenum) {
+    match mode {
+        BlendMode::Normal => (gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Screen => (gl::ONE, gl::ONE_MINUS_SRC_COLOR),
+        BlendMode::Additive => (gl::ONE, gl::ONE),
+        // The shader does the blending, and only needs what is below added back.
+        BlendMode::Multiply | BlendMode::Overlay => (gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+    }
+}
+
+/// The value of the shaders' `mode` uniform for a batch with a backdrop.
+fn shader_mode(mode: BlendMode) -> GLint {
+    if mode == BlendMode::Multiply { 1 } else { 2 }
+}
+
+/// A linked shader program and the locations of its uniforms. Uniforms a program doesn't use
+/// have a location of -1, which GL ignores.
+struct Program {
+    id: GLuint,
+    tex: GLint,
+    backdrop: GLint,
+    mode: GLint,
+    opacity: GLint,
+}
+
+impl Program {
+    fn new(vertex_source: &str, fragment_source: &str) -> Result<Self, Box<dyn Error>> {
+        let vertex = compile_shader(gl::VERTEX_SHADER, vertex_source)?;
+        let fragment = compile_shader(gl::FRAGMENT_SHADER, fragment_source)?;
+        unsafe {
+            let id = gl::CreateProgram();
+            gl::AttachShader(id, vertex);
+            gl::AttachShader(id, fragment);
+            gl::LinkProgram(id);
+            gl::DeleteShader(vertex);
+            gl::DeleteShader(fragment);
+
+            let mut status = gl::FALSE as GLint;
+            gl::GetProgramiv(id, gl::LINK_STATUS, &mut status);
+            if status != gl::TRUE as GLint {
+                let log = info_log(id, gl::GetProgramiv, gl::GetProgramInfoLog);
+                gl::DeleteProgram(id);
+                return Err(SimpleError::new(format!("Failed to link shaders: {}", log)).into());
+            }
+
+            let uniform = |name: &str| {
+                let name = CString::new(name).expect("Uniform names have no nul bytes");
+                gl::GetUniformLocation(id, name.as_ptr())
+            };
+            Ok(Self {
+                id,
+                tex: uniform("tex"),
+                backdrop: uniform("backdrop"),
+                mode: uniform("mode"),
+                opacity: uniform("opacity"),
+            })
+        }
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+    }
+}
+
+fn compile_shader(kind: GLenum, source: &str) -> Result<GLuint, Box<dyn Error>> {
+    let source = CString::new(source)?;
+    unsafe {
+        let shader = gl::CreateShader(kind);
+        gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+        gl::CompileShader(shader);
+
+        let mut status = gl::FALSE as GLint;
+        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+        if status != gl::TRUE as GLint {
+            let log = info_log(shader, gl::GetShaderiv, gl::GetShaderInfoLog);
+            gl::DeleteShader(shader);
+            return Err(SimpleError::new(format!("Failed to compile shader: {}", log)).into());
+        }
+        Ok(shader)
+    }
+}
+
+/// Reads the info log of a shader or program.
+unsafe fn info_log(object: GLuint,
+                   get_parameter: unsafe fn(GLuint, GLenum, *mut GLint),
+                   get_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar)) -> String {
+    let mut length = 0;
+    get_parameter(object, gl::INFO_LOG_LENGTH, &mut length);
+    let mut log = vec![0u8; length.max(1) as usize];
+    get_log(object, length, ptr::null_mut(), log.as_mut_ptr() as *mut GLchar);
+    String::from_utf8_lossy(&log).trim_end_matches('\0').to_string()
+}
+
+/// An offscreen image that a `Group` is drawn into.
+struct Layer {
+    framebuffer: GLuint,
+    texture: GLuint,
+}
+
+impl Layer {
+    fn new(dimensions: [u32; 2], format: GLenum) -> Result<Self, Box<dyn Error>> {
+        let mut layer = Self { framebuffer: 0, texture: 0 };
+        unsafe {
+            layer.texture = create_texture(dimensions[0], dimensions[1], format, ptr::null());
+            gl::GenFramebuffers(1, &mut layer.framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, layer.framebuffer);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, layer.texture, 0);
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                return Err(SimpleError::new("Failed to create offscreen framebuffer").into());
+            }
+        }
+        Ok(layer)
+    }
+}
+
+impl Drop for Layer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+/// Creates a texture with linear filtering that clamps to its edges. `pixels` are RGBA8, or null
+/// to leave the texture uninitialised.
+unsafe fn create_texture(width: u32, height: u32, format: GLenum, pixels: *const u8) -> GLuint {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, format as GLint, width as GLsizei, height as GLsizei, 0,
+                   gl::RGBA, gl::UNSIGNED_BYTE, pixels as *const _);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    texture
+}
+
+/// Uploads the (`x`, `y`, `width`, `height`) area of `texture` into the GL texture `id`.
+unsafe fn update_texture(id: GLuint, texture: &Texture, [x, y, width, height]: [u32; 4]) {
+    let pixels = texture.pixels();
+    let start = ((y * texture.width() + x) * 4) as usize;
+    gl::BindTexture(gl::TEXTURE_2D, id);
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, texture.width() as GLint);
+    gl::TexSubImage2D(gl::TEXTURE_2D, 0, x as GLint, y as GLint, width as GLsizei, height as GLsizei,
+                      gl::RGBA, gl::UNSIGNED_BYTE, pixels[start..].as_ptr() as *const _);
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+}
+
+/// Keeps every texture drawn in the previous frame resident on the GPU.
+struct TextureCache {
+    /// Either `SRGB8_ALPHA8`, so that sampling decodes to linear, or `RGBA8` when drawing
+    /// happens in sRGB space.
+    format: GLenum,
+    /// Bound in place of a texture for untextured batches.
+    blank: Arc<Texture>,
+    /// Each GL texture, and the version of the texture it holds.
+    textures: HashMap<u64, (GLuint, u64)>,
+    used: HashSet<u64>,
+}
+
+impl TextureCache {
+    fn new(format: GLenum) -> Self {
+        Self {
+            format,
+            blank: Texture::from_rgba8(1, 1, vec![255; 4]),
+            textures: HashMap::new(),
+            used: HashSet::new(),
+        }
+    }
+
+    /// Returns the GL texture for `texture` (or the blank texture), uploading it, or whatever
+    /// changed since it was last uploaded, if necessary.
+    fn get(&mut self, texture: Option<&Arc<Texture>>) -> GLuint {
+        let texture = texture.unwrap_or(&self.blank);
+        self.used.insert(texture.id());
+        let format = self.format;
+        let version = texture.version();
+        let (id, uploaded) = self.textures.entry(texture.id()).or_insert_with(|| unsafe {
+            (create_texture(texture.width(), texture.height(), format, texture.pixels().as_ptr()), version)
+        });
+        if *uploaded != version {
+            let area = texture.changed_since(*uploaded).unwrap_or([0, 0, texture.width(), texture.height()]);
+            unsafe { update_texture(*id, texture, area) };
+            *uploaded = version;
+        }
+        *id
+    }
+
+    /// Frees every texture that wasn't drawn since the last call.
+    fn end_frame(&mut self) {
+        let used = mem::take(&mut self.used);
+        self.textures.retain(|id, (texture, _)| {
+            if !used.contains(id) {
+                unsafe { gl::DeleteTextures(1, texture) };
+            }
+            used.contains(id)
+        });
+    }
+}
+
+impl Drop for TextureCache {
+    fn drop(&mut self) {
+        for (texture, _) in self.textures.values() {
+            unsafe { gl::DeleteTextures(1, texture) };
+        }
+    }
+}
+
+const VERTEX_SHADER: &str = "
+        #version 330 core
+
+        layout(location = 0) in vec3 position;
+        layout(location = 1) in vec2 tex_coord;
+        layout(location = 2) in vec4 colour;
+
+        out vec4 fragColour;
+        out vec2 fragTexCoord;
+
+        void main() {
+            gl_Position = vec4(position, 1.0);
+            fragColour = colour;
+            fragTexCoord = tex_coord;
+        }
+";
+
+const FRAGMENT_SHADER: &str = "
+        #version 330 core
+
+        in vec4 fragColour;
+        in vec2 fragTexCoord;
+        out vec4 f_color;
+
+        uniform sampler2D tex;
+        uniform sampler2D backdrop;
+        // 0 draws normally, 1 multiplies with the backdrop and 2 overlays it.
+        uniform int mode;
+
+        // Blends the premultiplied `source` with `below` following the W3C compositing spec,
+        // leaving out the share of `below` that blending then adds.
+        vec4 blend(vec4 source, vec4 below) {
+            vec3 cs = source.rgb / max(source.a, 1e-6);
+            vec3 cb = below.rgb / max(below.a, 1e-6);
+            vec3 blended = mode == 1
+                ? cs * cb
+                : mix(2.0 * cs * cb, 1.0 - 2.0 * (1.0 - cs) * (1.0 - cb), step(0.5, cb));
+            return vec4(source.rgb * (1.0 - below.a) + source.a * below.a * blended, source.a);
+        }
+
+        void main() {
+            // Premultiply here rather than on upload, so that textures decode from sRGB
+            // before being multiplied.
+            vec4 texel = texture(tex, fragTexCoord);
+            f_color = fragColour * vec4(texel.rgb * texel.a, texel.a);
+            if (mode != 0) {
+                f_color = blend(f_color, texelFetch(backdrop, ivec2(gl_FragCoord.xy), 0));
+            }
+        }
+";
+
+/// Like `VERTEX_SHADER`, but samples the layer at the same place on screen that it is drawn.
+const COMPOSITE_VERTEX_SHADER: &str = "
+        #version 330 core
+
+        layout(location = 0) in vec3 position;
+        layout(location = 1) in vec2 tex_coord;
+        layout(location = 2) in vec4 colour;
+
+        out vec4 fragColour;
+        out vec2 fragTexCoord;
+
+        void main() {
+            gl_Position = vec4(position, 1.0);
+            fragColour = colour;
+            fragTexCoord = position.xy * 0.5 + 0.5;
+        }
+";
+
+const COMPOSITE_FRAGMENT_SHADER: &str = "
+        #version 330 core
+
+        in vec4 fragColour;
+        in vec2 fragTexCoord;
+        out vec4 f_color;
+
+        uniform sampler2D tex;
+        uniform sampler2D backdrop;
+        uniform float opacity;
+        uniform int mode;
+
+        // The same as in `FRAGMENT_SHADER`.
+        vec4 blend(vec4 source, vec4 below) {
+            vec3 cs = source.rgb / max(source.a, 1e-6);
+            vec3 cb = below.rgb / max(below.a, 1e-6);
+            vec3 blended = mode == 1
+                ? cs * cb
+                : mix(2.0 * cs * cb, 1.0 - 2.0 * (1.0 - cs) * (1.0 - cb), step(0.5, cb));
+            return vec4(source.rgb * (1.0 - below.a) + source.a * below.a * blended, source.a);
+        }
+
+        void main() {
+            // The layer is already premultiplied, so fading it scales every channel.
+            f_color = opacity * fragColour * texture(tex, fragTexCoord);
+            if (mode != 0) {
+                f_color = blend(f_color, texelFetch(backdrop, ivec2(gl_FragCoord.xy), 0));
+            }
+        }
+";
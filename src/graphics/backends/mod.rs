@@ -1 +1,119 @@
-pub mod vk;
\ No newline at end of file
+use std::error::Error;
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+use crate::graphics::Colour;
+use crate::graphics::{GfxProvider, VertexProducer};
+use crate::graphics::config::{BackendKind, Config};
+
+#[cfg(feature = "opengl")]
+pub mod gl;
+pub mod software;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+#[cfg(feature = "vulkan")]
+pub mod vk;
+
+/// Whichever backend `Config::init` managed to start. There's only ever one, so the size of the
+/// largest variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Backend {
+    #[cfg(feature = "vulkan")]
+    Vulkan(vk::VulkanBackend),
+    #[cfg(feature = "opengl")]
+    OpenGl(gl::GlBackend),
+    Software(software::SoftwareBackend),
+    #[cfg(feature = "terminal")]
+    Terminal(terminal::TerminalBackend),
+}
+
+impl Backend {
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(_) => BackendKind::Vulkan,
+            #[cfg(feature = "opengl")]
+            Backend::OpenGl(_) => BackendKind::OpenGl,
+            Backend::Software(_) => BackendKind::Software,
+            #[cfg(feature = "terminal")]
+            Backend::Terminal(_) => BackendKind::Terminal,
+        }
+    }
+}
+
+impl GfxProvider for Backend {
+    /// Starts a backend with the default `Config`.
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Config::new().init()
+    }
+
+    fn show_fps(self) -> Self {
+        match self {
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(backend) => Backend::Vulkan(backend.show_fps()),
+            #[cfg(feature = "opengl")]
+            Backend::OpenGl(backend) => Backend::OpenGl(backend.show_fps()),
+            Backend::Software(backend) => Backend::Software(backend.show_fps()),
+            #[cfg(feature = "terminal")]
+            Backend::Terminal(backend) => Backend::Terminal(backend.show_fps()),
+        }
+    }
+
+    fn max_fps(self, fps: Option<u32>) -> Self {
+        match self {
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(backend) => Backend::Vulkan(backend.max_fps(fps)),
+            #[cfg(feature = "opengl")]
+            Backend::OpenGl(backend) => Backend::OpenGl(backend.max_fps(fps)),
+            Backend::Software(backend) => Backend::Software(backend.max_fps(fps)),
+            #[cfg(feature = "terminal")]
+            Backend::Terminal(backend) => Backend::Terminal(backend.max_fps(fps)),
+        }
+    }
+
+    fn run(self, vertex_producer: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>> {
+        match self {
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan(backend) => backend.run(vertex_producer),
+            #[cfg(feature = "opengl")]
+            Backend::OpenGl(backend) => backend.run(vertex_producer),
+            Backend::Software(backend) => backend.run(vertex_producer),
+            #[cfg(feature = "terminal")]
+            Backend::Terminal(backend) => backend.run(vertex_producer),
+        }
+    }
+}
+
+/// Saves a frame read back from a window as a timestamped PNG in `directory`. `pixels` are
+/// premultiplied RGBA8, as for `unpremultiply`.
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+pub(crate) fn save_screenshot(mut pixels: Vec<u8>, width: u32, height: u32, srgb: bool,
+                              directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    unpremultiply(&mut pixels, srgb);
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = directory.join(format!("screenshot-{}.png", timestamp));
+    image::save_buffer(&path, &pixels, width, height, image::ColorType::RGBA(8))?;
+    Ok(path)
+}
+
+/// Converts pixels read back from an image holding premultiplied colours to straight alpha. If
+/// `srgb` is set, the colours were premultiplied in linear space.
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+pub(crate) fn unpremultiply(pixels: &mut [u8], srgb: bool) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as f32 / 255.;
+        if alpha == 0. {
+            continue;
+        }
+        let colour = Colour::rgb8(pixel[0], pixel[1], pixel[2]);
+        let (r, g, b, _) = if srgb { colour.to_linear() } else { colour }.rgba();
+        let colour = Colour::Rgba(r / alpha, g / alpha, b / alpha, 1.);
+        let (r, g, b, _) = if srgb { colour.to_srgb() } else { colour }.rgba();
+        for (channel, value) in pixel.iter_mut().zip([r, g, b].iter()) {
+            *channel = (value.clamp(0., 1.) * 255.).round() as u8;
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::graphics::*;
+use crate::graphics::texture::Texture;
+use crate::graphics::util::FrameLimiter;
+use crate::graphics::window::WindowOptions;
+
+/// Draws on the CPU into an in-memory image, with no window or GPU.
+///
+/// Colours are blended in linear light with premultiplied alpha, as the Vulkan backend does with
+/// an sRGB swapchain, and every blend mode follows the W3C compositing spec as the GPU backends'
+/// blend factors and backdrop shaders do, so they should agree closely. Triangles are drawn in
+/// order without a depth test, and edges aren't antialiased.
+pub struct SoftwareBackend {
+    show_fps: bool,
+    max_fps: Option<u32>,
+    width: u32,
+    height: u32,
+    frames: Option<u64>,
+    clear_colour: Colour,
+    canvas: Canvas,
+    textures: TextureCache,
+}
+
+impl SoftwareBackend {
+    /// Draws into an image `width` by `height` pixels in size, which is also the window size
+    /// reported to the `VertexProducer`.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.resize(width, height);
+        self
+    }
+
+    /// Like `size`, for a backend that is already in use.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.canvas = Canvas::new(width, height);
+    }
+
+    /// How many frames `run` draws before returning. `None` keeps drawing forever.
+    pub fn frames(mut self, frames: Option<u64>) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    /// The colour each frame starts from.
+    pub fn clear_colour(mut self, colour: Colour) -> Self {
+        self.clear_colour = colour;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Draws a single frame, replacing the previous one.
+    pub fn render(&mut self, vertex_producer: &mut dyn VertexProducer) {
+        let data = vertex_producer.get_data(RuntimeParams {
+            window_width: self.width as u16,
+            window_height: self.height as u16,
+        });
+        self.canvas.clear(convert_colour(self.clear_colour));
+        let mut canvas = std::mem::replace(&mut self.canvas, Canvas::new(0, 0));
+        self.draw(&mut canvas, &data);
+        self.canvas = canvas;
+        self.textures.end_frame();
+    }
+
+    /// The last frame drawn, as tightly-packed, non-premultiplied sRGB RGBA8 pixels.
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.canvas.pixels.len() * 4);
+        for &[r, g, b, a] in self.canvas.pixels.iter() {
+            let (r, g, b, a) = if a > 0. {
+                Colour::Rgba(r / a, g / a, b / a, a).to_srgb().rgba()
+            } else {
+                (0., 0., 0., 0.)
+            };
+            pixels.extend([r, g, b, a].iter().map(|c| (c.clamp(0., 1.) * 255.).round() as u8));
+        }
+        pixels
+    }
+
+    /// Saves the last frame drawn as a PNG.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        image::save_buffer(path, &self.pixels(), self.width, self.height, image::ColorType::RGBA(8))?;
+        Ok(())
+    }
+
+    /// Draws `data` onto `canvas`, drawing each group onto a canvas of its own first.
+    fn draw(&mut self, canvas: &mut Canvas, data: &RenderData) {
+        let colours: Vec<[f32; 4]> = data.colours.iter().map(|col| convert_colour(*col)).collect();
+        for batch in data.batches.iter() {
+            let layer;
+            let source = match (&batch.group, &batch.texture) {
+                (Some(group), _) => {
+                    let mut group_canvas = Canvas::new(self.width, self.height);
+                    self.draw(&mut group_canvas, &group.data);
+                    layer = group_canvas;
+                    Source::Layer(&layer, group.opacity)
+                },
+                (None, Some(texture)) => Source::Texture(self.textures.get(texture)),
+                (None, None) => Source::Colour,
+            };
+            let clip = canvas.clip_bounds(batch.clip);
+            for triangle in data.indices[batch.start..batch.start + batch.count].chunks_exact(3) {
+                let corner = |i: usize| {
+                    let index = triangle[i] as usize;
+                    let (x, y) = match data.vertices[index] {
+                        Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => (x, y),
+                    };
+                    Corner { x, y, colour: colours[index], tex_coord: data.tex_coords[index] }
+                };
+                canvas.fill_triangle([corner(0), corner(1), corner(2)], clip, &source, batch.blend);
+            }
+        }
+    }
+}
+
+impl GfxProvider for SoftwareBackend {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        let (width, height) = (800, 600);
+        Ok(Self {
+            show_fps: false,
+            max_fps: None,
+            width,
+            height,
+            frames: Some(1),
+            clear_colour: Colour::Rgba(1., 0., 1., 1.),
+            canvas: Canvas::new(width, height),
+            textures: TextureCache::default(),
+        })
+    }
+
+    fn show_fps(mut self) -> Self {
+        self.show_fps = true;
+        self
+    }
+
+    fn max_fps(mut self, fps: Option<u32>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    fn run(mut self, mut vertex_producer: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>> {
+        let mut limiter = FrameLimiter::new(self.max_fps);
+        let mut t0 = Instant::now();
+        let mut updates = 0;
+        let fps_freq = 100;
+        while !matches!(self.frames, Some(frames) if updates >= frames) {
+            limiter.wait();
+            if let Some(WindowOptions { size: (width, height), .. }) = vertex_producer.window_options() {
+                if (width, height) != (self.width, self.height) {
+                    self.resize(width, height);
+                }
+            }
+            self.render(vertex_producer.as_mut());
+            updates += 1;
+            if self.show_fps && updates % fps_freq == 0 {
+                let ms = t0.elapsed().as_millis() as f32 / fps_freq as f32;
+                println!("{} fps", 1000.0 / ms);
+                t0 = Instant::now();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes and premultiplies a colour, ready for blending.
+fn convert_colour(col: Colour) -> [f32; 4] {
+    let (r, g, b, a) = col.to_linear().premultiplied().rgba();
+    [r, g, b, a]
+}
+
+/// A triangle corner in window coordinates, with its colour already converted.
+struct Corner {
+    x: f32,
+    y: f32,
+    colour: [f32; 4],
+    tex_coord: TexCoord,
+}
+
+/// What is multiplied with the vertex colours.
+enum Source<'a> {
+    Colour,
+    Texture(Arc<DecodedTexture>),
+    /// A group drawn at the same place on screen, faded by the given opacity.
+    Layer(&'a Canvas, f32),
+}
+
+/// An image in linear, premultiplied colours, row by row.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self { width, height, pixels: vec![[0.; 4]; (width * height) as usize] }
+    }
+
+    fn clear(&mut self, colour: [f32; 4]) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = colour;
+        }
+    }
+
+    /// The pixels that can be drawn to, as (left, top, right, bottom), with the clip rectangle
+    /// rounded to whole pixels like a scissor box.
+    fn clip_bounds(&self, clip: Option<Rect>) -> (i64, i64, i64, i64) {
+        let (width, height) = (self.width as i64, self.height as i64);
+        match clip {
+            Some(clip) => {
+                let left = clip.x.round() as i64;
+                let top = clip.y.round() as i64;
+                let right = (clip.x + clip.width).round() as i64;
+                let bottom = (clip.y + clip.height).round() as i64;
+                (left.max(0), top.max(0), right.min(width), bottom.min(height))
+            },
+            None => (0, 0, width, height),
+        }
+    }
+
+    /// Fills every pixel whose centre is inside the triangle. Pixels on an edge shared by two
+    /// triangles belong to exactly one of them, so translucent meshes don't double up.
+    fn fill_triangle(&mut self, corners: [Corner; 3], clip: (i64, i64, i64, i64), source: &Source, blend: BlendMode) {
+        let [a, mut b, mut c] = corners;
+        let mut area = edge(&a, &b, c.x, c.y);
+        if area == 0. {
+            return;
+        }
+        if area < 0. {
+            std::mem::swap(&mut b, &mut c);
+            area = -area;
+        }
+
+        let left = (a.x.min(b.x).min(c.x).floor() as i64).max(clip.0);
+        let top = (a.y.min(b.y).min(c.y).floor() as i64).max(clip.1);
+        let right = (a.x.max(b.x).max(c.x).ceil() as i64).min(clip.2);
+        let bottom = (a.y.max(b.y).max(c.y).ceil() as i64).min(clip.3);
+
+        for y in top..bottom {
+            for x in left..right {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let weights = [edge(&b, &c, px, py), edge(&c, &a, px, py), edge(&a, &b, px, py)];
+                let owned = [owns_edge(&b, &c), owns_edge(&c, &a), owns_edge(&a, &b)];
+                if weights.iter().zip(owned.iter()).any(|(&w, &owned)| w < 0. || (w == 0. && !owned)) {
+                    continue;
+                }
+                let [wa, wb, wc] = [weights[0] / area, weights[1] / area, weights[2] / area];
+
+                let mut colour = [0.; 4];
+                for (i, channel) in colour.iter_mut().enumerate() {
+                    *channel = wa * a.colour[i] + wb * b.colour[i] + wc * c.colour[i];
+                }
+                let texel = match source {
+                    Source::Colour => [1.; 4],
+                    Source::Texture(texture) => {
+                        let u = wa * a.tex_coord.0 + wb * b.tex_coord.0 + wc * c.tex_coord.0;
+                        let v = wa * a.tex_coord.1 + wb * b.tex_coord.1 + wc * c.tex_coord.1;
+                        texture.sample(u, v)
+                    },
+                    Source::Layer(layer, opacity) => {
+                        let [r, g, b, a] = layer.pixels[(y * layer.width as i64 + x) as usize];
+                        [r * opacity, g * opacity, b * opacity, a * opacity]
+                    },
+                };
+                for (channel, t) in colour.iter_mut().zip(texel.iter()) {
+                    *channel *= t;
+                }
+
+                let pixel = &mut self.pixels[(y * self.width as i64 + x) as usize];
+                *pixel = blend_pixel(colour, *pixel, blend);
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the triangle (`from`, `to`, (`x`, `y`)).
+fn edge(from: &Corner, to: &Corner, x: f32, y: f32) -> f32 {
+    (to.x - from.x) * (y - from.y) - (to.y - from.y) * (x - from.x)
+}
+
+/// Whether pixels exactly on the edge from `from` to `to` are drawn. A shared edge runs in
+/// opposite directions in the two triangles, so exactly one of them owns it.
+fn owns_edge(from: &Corner, to: &Corner) -> bool {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    dy > 0. || (dy == 0. && dx < 0.)
+}
+
+/// Composites the premultiplied `source` over `dest` following the W3C compositing spec, which
+/// blends where both are present and shows each through where only one is.
+fn blend_pixel(source: [f32; 4], dest: [f32; 4], mode: BlendMode) -> [f32; 4] {
+    let (sa, da) = (source[3], dest[3]);
+    let alpha = sa + da * (1. - sa);
+    if mode == BlendMode::Additive {
+        return [source[0] + dest[0], source[1] + dest[1], source[2] + dest[2], alpha];
+    }
+
+    let mut result = [0., 0., 0., alpha];
+    for i in 0..3 {
+        let (s, d) = (source[i], dest[i]);
+        let mixed = if sa > 0. && da > 0. {
+            let (cs, cb) = (s / sa, d / da);
+            let blended = match mode {
+                BlendMode::Normal | BlendMode::Additive => cs,
+                BlendMode::Multiply => cs * cb,
+                BlendMode::Screen => cs + cb - cs * cb,
+                BlendMode::Overlay => if cb <= 0.5 {
+                    2. * cs * cb
+                } else {
+                    1. - 2. * (1. - cs) * (1. - cb)
+                },
+            };
+            sa * da * blended
+        } else {
+            0.
+        };
+        result[i] = s * (1. - da) + d * (1. - sa) + mixed;
+    }
+    result
+}
+
+/// A texture decoded to linear, premultiplied colours.
+#[derive(Clone)]
+struct DecodedTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+    /// The version of the texture these pixels came from.
+    version: u64,
+}
+
+impl DecodedTexture {
+    fn new(texture: &Texture) -> Self {
+        let version = texture.version();
+        let pixels = texture.pixels().chunks_exact(4)
+            .map(|p| convert_colour(Colour::rgba8(p[0], p[1], p[2], p[3])))
+            .collect();
+        Self { width: texture.width(), height: texture.height(), pixels, version }
+    }
+
+    /// Decodes whatever changed in `texture` since these pixels came from it.
+    fn update(&mut self, texture: &Texture) {
+        let version = texture.version();
+        let [x, y, width, height] = match texture.changed_since(self.version) {
+            Some(area) => area,
+            None => {
+                *self = Self::new(texture);
+                return;
+            }
+        };
+        let source = texture.pixels();
+        for row in y..y + height {
+            for column in x..x + width {
+                let i = (row * self.width + column) as usize;
+                let p = &source[i * 4..i * 4 + 4];
+                self.pixels[i] = convert_colour(Colour::rgba8(p[0], p[1], p[2], p[3]));
+            }
+        }
+        self.version = version;
+    }
+
+    /// Samples with bilinear filtering, clamping to the edges.
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        if self.pixels.is_empty() {
+            return [0.; 4];
+        }
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: f32, y: f32| {
+            let x = (x.max(0.) as u32).min(self.width - 1);
+            let y = (y.max(0.) as u32).min(self.height - 1);
+            self.pixels[(y * self.width + x) as usize]
+        };
+        let (p00, p10, p01, p11) = (texel(x0, y0), texel(x0 + 1., y0), texel(x0, y0 + 1.), texel(x0 + 1., y0 + 1.));
+
+        let mut result = [0.; 4];
+        for (i, channel) in result.iter_mut().enumerate() {
+            let top = p00[i] + (p10[i] - p00[i]) * fx;
+            let bottom = p01[i] + (p11[i] - p01[i]) * fx;
+            *channel = top + (bottom - top) * fy;
+        }
+        result
+    }
+}
+
+/// Keeps every texture drawn in the previous frame decoded.
+#[derive(Default)]
+struct TextureCache {
+    textures: HashMap<u64, Arc<DecodedTexture>>,
+    used: HashSet<u64>,
+}
+
+impl TextureCache {
+    fn get(&mut self, texture: &Texture) -> Arc<DecodedTexture> {
+        self.used.insert(texture.id());
+        let decoded = self.textures.entry(texture.id())
+            .or_insert_with(|| Arc::new(DecodedTexture::new(texture)));
+        if decoded.version != texture.version() {
+            Arc::make_mut(decoded).update(texture);
+        }
+        decoded.clone()
+    }
+
+    /// Frees every texture that wasn't drawn since the last call.
+    fn end_frame(&mut self) {
+        let used = std::mem::take(&mut self.used);
+        self.textures.retain(|id, _| used.contains(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scene(RenderData);
+
+    impl VertexProducer for Scene {
+        fn get_data(&mut self, _params: RuntimeParams) -> RenderData {
+            self.0.clone()
+        }
+    }
+
+    fn square(x: f32, y: f32, size: f32, colour: Colour) -> RenderData {
+        let vertices = Vertex::from_xy(&[(x, y), (x, y + size), (x + size, y), (x + size, y + size)]);
+        RenderData::new(vertices, vec![colour; 4], vec![0, 1, 2, 2, 3, 1])
+    }
+
+    fn draw(data: RenderData, clear_colour: Colour) -> SoftwareBackend {
+        let mut backend = SoftwareBackend::new().unwrap().size(4, 4).clear_colour(clear_colour);
+        backend.render(&mut Scene(data));
+        backend
+    }
+
+    fn pixel(backend: &SoftwareBackend, x: u32, y: u32) -> [u8; 4] {
+        let pixels = backend.pixels();
+        let i = ((y * backend.width() + x) * 4) as usize;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    }
+
+    /// A grey with the given linear intensity, as sRGB.
+    fn grey(linear: f32) -> Colour {
+        Colour::Rgba(linear, linear, linear, 1.).to_srgb()
+    }
+
+    fn assert_close(actual: [u8; 4], expected: Colour) {
+        let (r, g, b, a) = expected.rgba();
+        let expected = [r, g, b, a].iter().map(|c| (c.clamp(0., 1.) * 255.).round() as i32).collect::<Vec<_>>();
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert!((*actual as i32 - expected).abs() <= 1, "{:?} isn't close to {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn fills_a_quad() {
+        let backend = draw(square(1., 1., 2., Colour::red()), Colour::black());
+        for y in 0..4 {
+            for x in 0..4 {
+                let inside = (1..3).contains(&x) && (1..3).contains(&y);
+                let expected = if inside { [255, 0, 0, 255] } else { [0, 0, 0, 255] };
+                assert_eq!(pixel(&backend, x, y), expected, "at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fades_groups_as_a_whole() {
+        // Overlapping squares in a group don't show through each other.
+        let mut data = square(0., 0., 4., Colour::white());
+        data.append(square(0., 0., 2., Colour::white()));
+        let backend = draw(data.group(0.5), Colour::black());
+        assert_close(pixel(&backend, 0, 0), grey(0.5));
+        assert_close(pixel(&backend, 3, 3), grey(0.5));
+    }
+
+    #[test]
+    fn clips_to_whole_pixels() {
+        let mut data = square(0., 0., 4., Colour::white());
+        data.clip(Rect::new(1., 0., 2., 4.));
+        let backend = draw(data, Colour::black());
+        for x in 0..4 {
+            let expected = if x == 1 || x == 2 { [255; 4] } else { [0, 0, 0, 255] };
+            assert_eq!(pixel(&backend, x, 2), expected, "at x = {}", x);
+        }
+    }
+
+    #[test]
+    fn blends_each_mode() {
+        let (source, dest) = (0.5, 0.25);
+        for &mode in BlendMode::all().iter() {
+            let expected = match mode {
+                BlendMode::Normal => source,
+                BlendMode::Multiply => source * dest,
+                BlendMode::Screen => source + dest - source * dest,
+                BlendMode::Additive => source + dest,
+                BlendMode::Overlay => 2. * source * dest,
+            };
+            let mut data = square(0., 0., 4., grey(source));
+            data.blend(mode);
+            let backend = draw(data, grey(dest));
+            assert_close(pixel(&backend, 1, 1), grey(expected));
+        }
+    }
+
+    #[test]
+    fn blends_only_where_both_are_present() {
+        // Multiplying over transparency leaves the source as it is.
+        let mut data = square(0., 0., 4., Colour::red());
+        data.blend(BlendMode::Multiply);
+        let backend = draw(data, Colour::Rgba(0., 0., 0., 0.));
+        assert_eq!(pixel(&backend, 1, 1), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn multiplies_translucent_areas_by_the_spec() {
+        // Over half-transparent grey, half the source shows as it is and half is multiplied:
+        // 0.5 · 0.5 + 0.5 · (0.5 · 0.25) in linear light. Blend factors alone would give only the
+        // multiplied half.
+        let mut data = square(0., 0., 4., grey(0.5));
+        data.blend(BlendMode::Multiply);
+        let backend = draw(data, grey(0.25).with_alpha(0.5));
+        assert_close(pixel(&backend, 1, 1), grey(0.3125));
+    }
+}
@@ -0,0 +1,301 @@
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyModifiers, MouseEvent};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen, SetTitle};
+use crossterm::{execute, queue};
+
+use std::error::Error;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+use crate::graphics::*;
+use crate::graphics::backends::software::SoftwareBackend;
+use crate::graphics::input::{InputEvent, Key, Modifiers, MouseButton};
+use crate::graphics::util::FrameLimiter;
+use crate::graphics::window::WindowOptions;
+
+/// The frame rate when no cap is set, since terminals are slow to draw to.
+const DEFAULT_MAX_FPS: u32 = 60;
+
+/// Draws in a terminal with 24-bit colour, for use over SSH.
+///
+/// Each character cell is drawn as two pixels, one above the other, using the upper half block
+/// character with different foreground and background colours. So the window is as many pixels
+/// wide as the terminal has columns, and twice as tall as it has rows. Frames are drawn with
+/// the software backend, and only cells that change are sent. Press Ctrl+C to quit.
+pub struct TerminalBackend {
+    show_fps: bool,
+    /// Used instead of `DEFAULT_MAX_FPS`.
+    max_fps: Option<u32>,
+    /// Shown in the terminal's title bar.
+    title: String,
+    software: SoftwareBackend,
+    columns: u16,
+    rows: u16,
+    /// The colours of the top and bottom of each cell on screen, row by row.
+    cells: Vec<([u8; 3], [u8; 3])>,
+}
+
+impl TerminalBackend {
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sends the cells that changed since the last frame.
+    fn present(&mut self, stdout: &mut Stdout) -> Result<(), Box<dyn Error>> {
+        let pixels = self.software.pixels();
+        let width = self.columns as usize;
+        let pixel = |x: usize, y: usize| {
+            let i = (y * width + x) * 4;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+
+        let mut cells = Vec::with_capacity(self.cells.len());
+        // Where the terminal's cursor and colours are, so they are only sent when they change.
+        let mut cursor = None;
+        let mut colours = None;
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let y = row as usize * 2;
+                let cell = (pixel(column as usize, y), pixel(column as usize, y + 1));
+                cells.push(cell);
+                if self.cells.get(cells.len() - 1) == Some(&cell) {
+                    continue;
+                }
+
+                if cursor != Some((column, row)) {
+                    queue!(stdout, MoveTo(column, row))?;
+                }
+                if colours != Some(cell) {
+                    let ([r, g, b], [br, bg, bb]) = cell;
+                    queue!(stdout,
+                           SetForegroundColor(Color::Rgb { r, g, b }),
+                           SetBackgroundColor(Color::Rgb { r: br, g: bg, b: bb }))?;
+                    colours = Some(cell);
+                }
+                queue!(stdout, Print('▀'))?;
+                cursor = Some((column + 1, row));
+            }
+        }
+        stdout.flush()?;
+        self.cells = cells;
+        Ok(())
+    }
+
+    /// Matches the window to the size of the terminal, and redraws everything if it changed.
+    fn update_size(&mut self) -> Result<(), Box<dyn Error>> {
+        let (columns, rows) = terminal::size()?;
+        if (columns, rows) != (self.columns, self.rows) {
+            self.columns = columns;
+            self.rows = rows;
+            self.software.resize(columns as u32, rows as u32 * 2);
+            self.cells.clear();
+        }
+        Ok(())
+    }
+}
+
+impl GfxProvider for TerminalBackend {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            show_fps: false,
+            max_fps: None,
+            title: WindowOptions::default().title,
+            software: SoftwareBackend::new()?.clear_colour(Colour::black()),
+            columns: 0,
+            rows: 0,
+            cells: Vec::new(),
+        })
+    }
+
+    fn show_fps(mut self) -> Self {
+        self.show_fps = true;
+        self
+    }
+
+    /// With no cap, frames are still drawn no more than `DEFAULT_MAX_FPS` times per second.
+    fn max_fps(mut self, fps: Option<u32>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    /// Draws until Ctrl+C is pressed. The fps, if shown, goes in the terminal's title. Window
+    /// options other than the title are ignored, since the window is the terminal.
+    fn run(mut self, mut vertex_producer: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>> {
+        let mut stdout = io::stdout();
+        let _raw = RawTerminal::enter(&mut stdout)?;
+        execute!(stdout, SetTitle(&self.title))?;
+        let mut limiter = FrameLimiter::new(Some(self.max_fps.unwrap_or(DEFAULT_MAX_FPS)));
+
+        let mut t0 = Instant::now();
+        let mut updates = 0;
+        let fps_freq = 100;
+        loop {
+            limiter.wait();
+            if self.show_fps {
+                updates += 1;
+                if updates % fps_freq == 0 {
+                    let ms = t0.elapsed().as_millis() as f32 / fps_freq as f32;
+                    execute!(stdout, SetTitle(&format!("{} ({} fps)", self.title, 1000.0 / ms)))?;
+                    t0 = Instant::now();
+                }
+            }
+
+            if let Some(options) = vertex_producer.window_options() {
+                if options.title != self.title {
+                    self.title = options.title;
+                    execute!(stdout, SetTitle(&self.title))?;
+                }
+            }
+
+            self.update_size()?;
+            self.software.render(vertex_producer.as_mut());
+            self.present(&mut stdout)?;
+
+            // Handle everything that's waiting, then draw again.
+            while event::poll(Duration::from_secs(0))? {
+                match event::read()? {
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers })
+                        if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    event::Event::Key(key) => for event in convert_key(key) {
+                        vertex_producer.handle_event(event);
+                    },
+                    event::Event::Mouse(mouse) => vertex_producer.handle_event(convert_mouse(mouse)),
+                    // The new size is picked up before the next frame.
+                    event::Event::Resize(..) => (),
+                }
+            }
+        }
+    }
+}
+
+/// Puts the terminal into raw mode on the alternate screen with mouse reporting, and puts it
+/// back when dropped, even on an error.
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter(stdout: &mut Stdout) -> Result<Self, Box<dyn Error>> {
+        terminal::enable_raw_mode()?;
+        let raw = RawTerminal;
+        execute!(stdout, EnterAlternateScreen, Hide, EnableMouseCapture)?;
+        Ok(raw)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        // There's nothing to be done about errors here.
+        let _ = execute!(io::stdout(), DisableMouseCapture, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A key press, followed by the character it typed if there is one. Terminals report both
+/// together.
+fn convert_key(key: KeyEvent) -> Vec<InputEvent> {
+    let mut modifiers = convert_modifiers(key.modifiers);
+    let converted = match key.code {
+        KeyCode::Char(c) => {
+            let unshifted = unshift(c);
+            // Terminals don't always report shift along with the character it changed.
+            modifiers.shift |= unshifted != c;
+            Key::Character(unshifted)
+        },
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Escape,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Tab | KeyCode::BackTab => Key::Tab,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::F(n) => Key::F(n),
+        KeyCode::Null => return Vec::new(),
+    };
+    let mut events = vec![InputEvent::Key { key: converted, modifiers }];
+    if let KeyCode::Char(c) = key.code {
+        if !modifiers.ctrl && !modifiers.alt {
+            events.push(InputEvent::Char(c));
+        }
+    }
+    events
+}
+
+/// The character typed by the same key without shift, assuming a US layout as window backends
+/// do. Characters that aren't on such a keyboard are left alone.
+fn unshift(c: char) -> char {
+    const SHIFTED: &str = "~!@#$%^&*()_+{}|:\"<>?";
+    const UNSHIFTED: &str = "`1234567890-=[]\\;',./";
+    match SHIFTED.find(c) {
+        Some(i) => UNSHIFTED[i..].chars().next().unwrap(),
+        None => c.to_ascii_lowercase(),
+    }
+}
+
+/// Converts a mouse event, placing it in the middle of the cell it happened in.
+fn convert_mouse(mouse: MouseEvent) -> InputEvent {
+    let position = |column: u16, row: u16| (column as f32 + 0.5, row as f32 * 2. + 1.);
+    let button = |button| match button {
+        event::MouseButton::Left => MouseButton::Left,
+        event::MouseButton::Right => MouseButton::Right,
+        event::MouseButton::Middle => MouseButton::Middle,
+    };
+    match mouse {
+        MouseEvent::Down(pressed, column, row, _) => {
+            let (x, y) = position(column, row);
+            InputEvent::MouseDown { x, y, button: button(pressed) }
+        },
+        MouseEvent::Up(released, column, row, _) => {
+            let (x, y) = position(column, row);
+            InputEvent::MouseUp { x, y, button: button(released) }
+        },
+        // Terminals only report movement while a button is held.
+        MouseEvent::Drag(_, column, row, _) => {
+            let (x, y) = position(column, row);
+            InputEvent::MouseMove { x, y }
+        },
+        MouseEvent::ScrollDown(column, row, _) => {
+            let (x, y) = position(column, row);
+            InputEvent::Scroll { x, y, lines: 1. }
+        },
+        MouseEvent::ScrollUp(column, row, _) => {
+            let (x, y) = position(column, row);
+            InputEvent::Scroll { x, y, lines: -1. }
+        },
+    }
+}
+
+fn convert_modifiers(modifiers: KeyModifiers) -> Modifiers {
+    Modifiers {
+        shift: modifiers.contains(KeyModifiers::SHIFT),
+        ctrl: modifiers.contains(KeyModifiers::CONTROL),
+        alt: modifiers.contains(KeyModifiers::ALT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_keys_unshifted() {
+        let events = convert_key(KeyEvent { code: KeyCode::Char('!'), modifiers: KeyModifiers::empty() });
+        match events[0] {
+            InputEvent::Key { key, modifiers } => {
+                assert_eq!(key, Key::Character('1'));
+                assert!(modifiers.shift);
+            },
+            _ => panic!("Expected a key press"),
+        }
+        assert!(matches!(events[1], InputEvent::Char('!')));
+        assert_eq!(unshift('Q'), 'q');
+        assert_eq!(unshift('"'), '\'');
+        assert_eq!(unshift('é'), 'é');
+    }
+}
@@ -1,90 +1,698 @@
 use vulkano::device::Queue;
 use vulkano::swapchain::Surface;
 use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer };
+use vulkano::buffer::BufferAccess;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
 use vulkano::device::{Device, DeviceExtensions};
-use vulkano::format::Format;
+use vulkano::format::{ClearValue, Format};
 use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, Subpass, RenderPassAbstract};
-use vulkano::image::SwapchainImage;
+use vulkano::image::{Dimensions, ImageUsage, StorageImage, SwapchainImage};
 use vulkano::image::attachment::AttachmentImage;
-use vulkano::instance::Instance;
-use vulkano::instance::PhysicalDevice;
+use vulkano::instance::{Instance, InstanceExtensions};
+use vulkano::instance::{PhysicalDevice, PhysicalDeviceType, QueueFamily};
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor};
+use vulkano::pipeline::depth_stencil::{Compare, DepthStencil};
 use vulkano::pipeline::vertex::TwoBuffersDefinition;
-use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::viewport::{Scissor, Viewport};
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::swapchain::{AcquireError, PresentMode, SurfaceTransform, Swapchain, SwapchainCreationError};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::swapchain::{AcquireError, ColorSpace, CompositeAlpha, SurfaceTransform, Swapchain, SwapchainCreationError};
 use vulkano::swapchain;
 use vulkano::sync::{GpuFuture, FlushError};
 use vulkano::sync;
 
 use vulkano_win::VkSurfaceBuild;
 
-use winit::{EventsLoop, Window, WindowBuilder, Event, WindowEvent};
+use winit::{EventsLoop, Window, Event, WindowEvent};
 
 use simple_error::SimpleError;
 
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::iter;
 use std::time::Instant;
 use std::error::Error;
 use crate::graphics::*;
+use crate::graphics::backends::{save_screenshot, unpremultiply};
+use crate::graphics::config::{Config, DeviceInfo, DeviceKind, DevicePolicy, PresentMode};
+use crate::graphics::input::{winit_input, InputEvent, Key};
+use crate::graphics::texture::Texture;
+use crate::graphics::util::FrameLimiter;
+use crate::graphics::window::{winit_window, WindowOptions};
+
+winit_input!(winit);
+winit_window!(winit);
+
+type Pipeline = Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+type Framebuffers = Vec<Arc<dyn FramebufferAbstract + Send + Sync>>;
+type TextureSet = Arc<dyn DescriptorSet + Send + Sync>;
+type RenderPass = Arc<dyn RenderPassAbstract + Send + Sync>;
+
+/// Everything that has to be rebuilt when the window changes size.
+struct Targets {
+    /// One for each blend mode, since blending is fixed when a pipeline is built.
+    pipelines: HashMap<BlendMode, Pipeline>,
+    /// Draw the offscreen image of a `Group`.
+    composite_pipelines: HashMap<BlendMode, Pipeline>,
+    framebuffers: Framebuffers,
+    /// Shared by the swapchain framebuffers and the offscreen ones, since it is cleared for
+    /// every pass.
+    depth_buffer: Arc<AttachmentImage<Format>>,
+    /// With multisampling, everything is drawn here and then resolved into the real target. It
+    /// is shared in the same way as the depth buffer.
+    msaa_buffer: Option<Arc<AttachmentImage<Format>>>,
+    /// The offscreen images groups were drawn into last frame, to be drawn into again. They are
+    /// the size of the window, so they go when it resizes.
+    layers: Vec<Layer>,
+}
+
+/// The offscreen image a `Group` is drawn into.
+struct Layer {
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    /// Binds the image for compositing.
+    set: TextureSet,
+    /// Kept to bind as a backdrop as well.
+    image: Arc<AttachmentImage<Format>>,
+}
+
+/// The format of offscreen output. It is sRGB, so that headless frames match windowed ones.
+const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8Srgb;
+
+/// Where finished frames go. There is only ever one, so the size of `Window` doesn't matter.
+#[allow(clippy::large_enum_variant)]
+enum Output {
+    Window {
+        surface: Arc<Surface<Window>>,
+        swapchain: Arc<Swapchain<Window>>,
+        images: Vec<Arc<SwapchainImage<Window>>>,
+        events_loop: EventsLoop,
+        /// What the window was last set up with.
+        options: WindowOptions,
+    },
+    /// Frames are drawn into `image`, then copied into `buffer` to be read on the CPU.
+    Offscreen {
+        image: Arc<AttachmentImage<Format>>,
+        buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    },
+}
+
+/// Creates the render pass. With more than one sample, it draws to a multisampled colour
+/// attachment and resolves that into a single-sampled one, which goes last.
+fn create_render_pass(device: Arc<Device>, format: Format, samples: u32) -> Result<RenderPass, Box<dyn Error>> {
+    if samples > 1 {
+        Ok(Arc::new(vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    intermediary: {
+                        load: Clear,
+                        store: DontCare,
+                        format: format,
+                        samples: samples,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: samples,
+                    },
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [intermediary],
+                    depth_stencil: {depth},
+                    resolve: [color],
+                }
+            )?))
+    } else {
+        Ok(Arc::new(vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )?))
+    }
+}
+
+/// Keeps every texture drawn in the previous frame resident on the GPU.
+struct TextureCache {
+    queue: Arc<Queue>,
+    sampler: Arc<Sampler>,
+    /// Either `R8G8B8A8Srgb`, so that sampling decodes to linear, or `R8G8B8A8Unorm` when
+    /// drawing happens in sRGB space.
+    format: Format,
+    /// Bound in place of a texture for untextured batches.
+    blank: Arc<Texture>,
+    /// Each image, and the version of the texture it holds. Images stay allocated while their
+    /// texture keeps being drawn, and only the area that changed is copied into them again.
+    images: HashMap<u64, (Arc<StorageImage<Format>>, u64)>,
+    sets: HashMap<u64, TextureSet>,
+    used: HashSet<u64>,
+}
+
+impl TextureCache {
+    fn new(queue: Arc<Queue>, format: Format) -> Result<Self, Box<dyn Error>> {
+        let sampler = Sampler::new(queue.device().clone(), Filter::Linear, Filter::Linear,
+                                   MipmapMode::Nearest, SamplerAddressMode::ClampToEdge,
+                                   SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                   0.0, 1.0, 0.0, 0.0)?;
+        Ok(Self {
+            queue,
+            sampler,
+            format,
+            blank: Texture::from_rgba8(1, 1, vec![255; 4]),
+            images: HashMap::new(),
+            sets: HashMap::new(),
+            used: HashSet::new(),
+        })
+    }
+
+    /// Returns the descriptor set binding `texture` (or the blank texture) to `pipeline`,
+    /// uploading whatever changed first. Uploads are chained onto `future`.
+    fn get(&mut self, texture: Option<&Arc<Texture>>, pipeline: &Pipeline,
+           future: &mut Box<dyn GpuFuture>) -> Result<TextureSet, Box<dyn Error>> {
+        let texture = texture.unwrap_or(&self.blank);
+        self.used.insert(texture.id());
+
+        let version = texture.version();
+        let whole = [0, 0, texture.width(), texture.height()];
+        let (image, area) = match self.images.get(&texture.id()) {
+            Some((image, uploaded)) if *uploaded == version => (image.clone(), None),
+            Some((image, uploaded)) => (image.clone(), Some(texture.changed_since(*uploaded).unwrap_or(whole))),
+            None => {
+                let usage = ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() };
+                let image = StorageImage::with_usage(
+                    self.queue.device().clone(),
+                    Dimensions::Dim2d { width: texture.width(), height: texture.height() },
+                    self.format,
+                    usage,
+                    iter::once(self.queue.family()))?;
+                (image, Some(whole))
+            }
+        };
+        if let Some(area) = area {
+            self.upload(&image, texture, area, future)?;
+            self.images.insert(texture.id(), (image.clone(), version));
+        }
+
+        if let Some(set) = self.sets.get(&texture.id()) {
+            return Ok(set.clone());
+        }
+        let set = Arc::new(PersistentDescriptorSet::start(pipeline.clone(), 0)
+            .add_sampled_image(image, self.sampler.clone())?
+            .build()?) as TextureSet;
+        self.sets.insert(texture.id(), set.clone());
+        Ok(set)
+    }
+
+    /// Copies the (x, y, width, height) `area` of `texture` into `image`, in a command buffer
+    /// of its own since textures are fetched while a render pass is being recorded.
+    fn upload(&self, image: &Arc<StorageImage<Format>>, texture: &Texture, [x, y, width, height]: [u32; 4],
+              future: &mut Box<dyn GpuFuture>) -> Result<(), Box<dyn Error>> {
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let stride = texture.width() as usize * 4;
+        let pixels = texture.pixels();
+        let rows = (y as usize..(y + height) as usize)
+            .flat_map(|row| {
+                let start = row * stride + x as usize * 4;
+                pixels[start..start + width as usize * 4].iter().cloned()
+            })
+            .collect::<Vec<_>>();
+        let buffer = CpuAccessibleBuffer::from_iter(self.queue.device().clone(), BufferUsage::transfer_source(), rows.into_iter())?;
+        let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(self.queue.device().clone(), self.queue.family())?
+            .copy_buffer_to_image_dimensions(buffer, image.clone(), [x, y, 0], [width, height, 1], 0, 1, 0)?
+            .build()?;
+        let previous = std::mem::replace(future, Box::new(sync::now(self.queue.device().clone())));
+        *future = Box::new(previous.then_execute(self.queue.clone(), command_buffer)?);
+        Ok(())
+    }
+
+    /// Drops descriptor sets, which need rebuilding whenever the pipeline is recreated.
+    fn invalidate_sets(&mut self) {
+        self.sets.clear();
+    }
+
+    /// Frees every texture that wasn't drawn since the last call.
+    fn end_frame(&mut self) {
+        let used = std::mem::take(&mut self.used);
+        self.images.retain(|id, _| used.contains(id));
+        self.sets.retain(|id, _| used.contains(id));
+    }
+}
 
 pub struct VulkanBackend {
     show_fps: bool,
+    max_fps: Option<u32>,
+    /// Pressing the key saves the next frame as a PNG in the directory.
+    screenshot_key: Option<(Key, PathBuf)>,
     device: Arc<Device>,
     vs: vs::Shader,
     fs: fs::Shader,
-    swapchain: Arc<Swapchain<winit::Window>>,
-    images: Vec<Arc<SwapchainImage<Window>>>,
-    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-    surface: Arc<Surface<winit::Window>>,
+    composite_vs: composite_vs::Shader,
+    composite_fs: composite_fs::Shader,
+    backdrop_fs: backdrop_fs::Shader,
+    composite_backdrop_fs: composite_backdrop_fs::Shader,
+    output: Output,
+    /// Kept between calls to `render`. `run` keeps its own.
+    targets: Option<Targets>,
+    render_pass: RenderPass,
+    samples: u32,
+    /// Whether the swapchain encodes to sRGB itself, so that colours are blended in linear space.
+    srgb: bool,
     queue: Arc<Queue>,
-    events_loop: EventsLoop,
+    textures: TextureCache,
     phys_dims: [u32; 2],
     log_dims: [u32; 2],
 }
 
 impl VulkanBackend {
-    fn window_size_dependent_setup(&self) -> Result<(Arc<(dyn GraphicsPipelineAbstract + Send + Sync)>,
-                                              Vec<Arc<dyn FramebufferAbstract + Send + Sync>>),
-                                                    Box<dyn Error>>{
-        let dimensions = self.images[0].dimensions();
-        let depth_buffer = AttachmentImage::transient(
-            self.device.clone(),
-            dimensions,
-            Format::D16Unorm)?;
+    /// Creates a backend with no window, which draws `width` by `height` pixel frames into an
+    /// image in memory. It only needs a device that can draw, so it works on servers without a
+    /// display (e.g. with lavapipe). The device is chosen by `policy`, as for a window. Draw
+    /// frames with `render`, and read them with `pixels`.
+    pub fn headless(width: u32, height: u32, policy: &DevicePolicy) -> Result<Self, Box<dyn Error>> {
+        println!("Beginning headless Vulkan setup...");
+        let instance = Instance::new(None, &InstanceExtensions::none(), None)?;
+
+        let (physical, queue_family) = choose_device(&instance, None, policy)?;
+        println!("Using {}.", physical.name());
+
+        let (device, mut queues) = Device::new(physical, physical.supported_features(), &DeviceExtensions::none(),
+                                               [(queue_family, 0.5)].iter().cloned())?;
+        let queue = queues.next().ok_or(SimpleError::new("Failed to create queue"))?;
 
-        let framebuffers = self.images.iter().map(|image| {
-            let buf = Framebuffer::start(self.render_pass.clone())
-                .add(image.clone())?
+        let image = AttachmentImage::with_usage(
+            device.clone(),
+            [width, height],
+            OFFSCREEN_FORMAT,
+            ImageUsage { color_attachment: true, transfer_source: true, ..ImageUsage::none() })?;
+        let buffer = CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_destination(),
+                                                    vec![0u8; (width * height * 4) as usize].into_iter())?;
+
+        Self::with_output(device, queue, Output::Offscreen { image, buffer }, true, [width, height], [width, height])
+    }
+
+    /// Finishes setting up once the device and output exist.
+    fn with_output(device: Arc<Device>, queue: Arc<Queue>, output: Output, srgb: bool,
+                   phys_dims: [u32; 2], log_dims: [u32; 2]) -> Result<Self, Box<dyn Error>> {
+        let vs = vs::Shader::load(device.clone())?;
+        let fs = fs::Shader::load(device.clone())?;
+        let composite_vs = composite_vs::Shader::load(device.clone())?;
+        let composite_fs = composite_fs::Shader::load(device.clone())?;
+        let backdrop_fs = backdrop_fs::Shader::load(device.clone())?;
+        let composite_backdrop_fs = composite_backdrop_fs::Shader::load(device.clone())?;
+
+        let format = match &output {
+            Output::Window { swapchain, .. } => swapchain.format(),
+            Output::Offscreen { .. } => OFFSCREEN_FORMAT,
+        };
+        let render_pass = create_render_pass(device.clone(), format, 1)?;
+
+        let texture_format = if srgb { Format::R8G8B8A8Srgb } else { Format::R8G8B8A8Unorm };
+        let textures = TextureCache::new(queue.clone(), texture_format)?;
+
+        Ok(Self {
+            show_fps: false,
+            max_fps: None,
+            screenshot_key: None,
+            device,
+            vs,
+            fs,
+            composite_vs,
+            composite_fs,
+            backdrop_fs,
+            composite_backdrop_fs,
+            output,
+            targets: None,
+            render_pass,
+            samples: 1,
+            srgb,
+            queue,
+            textures,
+            phys_dims,
+            log_dims
+        })
+    }
+
+    /// Draws with `samples` samples per pixel, or with the highest count below that the device
+    /// supports. 1 turns multisampling off.
+    pub fn msaa(mut self, samples: u32) -> Self {
+        let limits = self.device.physical_device().limits();
+        let supported = limits.framebuffer_color_sample_counts() & limits.framebuffer_depth_sample_counts();
+        // Each supported count is a bit of the mask, so take the highest bit that isn't above it.
+        let mut count = samples.max(1).next_power_of_two();
+        if count > samples.max(1) {
+            count /= 2;
+        }
+        while count > 1 && supported & count == 0 {
+            count /= 2;
+        }
+        if count != samples {
+            println!("{}x MSAA is not supported; using {}x.", samples, count);
+        }
+        self.samples = count;
+        self
+    }
+
+    /// Draws a single frame into the offscreen image of a headless backend, and waits for it to
+    /// finish.
+    pub fn render(&mut self, vertex_producer: &mut dyn VertexProducer) -> Result<(), Box<dyn Error>> {
+        let (image, buffer) = match &self.output {
+            Output::Offscreen { image, buffer } => (image.clone(), buffer.clone()),
+            Output::Window { .. } => return Err("Only headless backends can render on demand".into()),
+        };
+        let mut targets = match self.targets.take() {
+            Some(targets) => targets,
+            None => {
+                if self.samples > 1 {
+                    self.render_pass = create_render_pass(self.device.clone(), OFFSCREEN_FORMAT, self.samples)?;
+                }
+                self.window_size_dependent_setup()?
+            }
+        };
+
+        let data = vertex_producer.get_data(RuntimeParams {
+            window_width: self.log_dims[0] as u16,
+            window_height: self.log_dims[1] as u16
+        });
+
+        let mut future = Box::new(sync::now(self.device.clone())) as Box<dyn GpuFuture>;
+        let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())?;
+        let command_buffer = self.record_frame(command_buffer, &data.with_backdrops(), &mut targets, 0, &mut future)?
+            .copy_image_to_buffer(image, buffer)?
+            .build()?;
+        self.textures.end_frame();
+        self.targets = Some(targets);
+
+        future.then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+        Ok(())
+    }
+
+    /// The last frame drawn by `render`, as tightly-packed, non-premultiplied sRGB RGBA8 pixels.
+    pub fn pixels(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &self.output {
+            Output::Offscreen { buffer, .. } => {
+                let mut pixels = buffer.read()?.to_vec();
+                unpremultiply(&mut pixels, true);
+                Ok(pixels)
+            },
+            Output::Window { .. } => Err("Only headless backends keep their pixels".into()),
+        }
+    }
+
+    /// Saves the last frame drawn by `render` as a PNG.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let [width, height] = self.dimensions();
+        image::save_buffer(path, &self.pixels()?, width, height, image::ColorType::RGBA(8))?;
+        Ok(())
+    }
+
+    /// While running, pressing `key` saves the frame as a timestamped PNG in `directory`.
+    pub fn screenshot_key<P: Into<PathBuf>>(mut self, key: Key, directory: P) -> Self {
+        self.screenshot_key = Some((key, directory.into()));
+        self
+    }
+
+    /// A buffer to copy a frame out of the swapchain into. It holds four bytes a pixel, so only
+    /// the 8-bit RGBA and BGRA formats that `save_screenshot` reads can be copied.
+    fn screenshot_buffer(&self) -> Result<Arc<CpuAccessibleBuffer<[u8]>>, Box<dyn Error>> {
+        let format = self.format();
+        if !matches!(format, Format::R8G8B8A8Srgb | Format::R8G8B8A8Unorm | Format::B8G8R8A8Srgb | Format::B8G8R8A8Unorm) {
+            return Err(SimpleError::new(format!("Can't read back frames in {:?} format", format)).into());
+        }
+        let [width, height] = self.dimensions();
+        Ok(CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::transfer_destination(),
+                                          vec![0u8; (width * height * 4) as usize].into_iter())?)
+    }
+
+    /// Saves a frame copied out of the swapchain into `buffer` as a PNG in `directory`.
+    fn save_screenshot(&self, buffer: &CpuAccessibleBuffer<[u8]>, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let mut pixels = buffer.read()?.to_vec();
+        if let Format::B8G8R8A8Srgb | Format::B8G8R8A8Unorm = self.format() {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        let [width, height] = self.dimensions();
+        save_screenshot(pixels, width, height, self.srgb, directory)
+    }
+
+    /// The format that frames are drawn in.
+    fn format(&self) -> Format {
+        match &self.output {
+            Output::Window { swapchain, .. } => swapchain.format(),
+            Output::Offscreen { .. } => OFFSCREEN_FORMAT,
+        }
+    }
+
+    /// The size of the images that frames are drawn into, in physical pixels.
+    fn dimensions(&self) -> [u32; 2] {
+        match &self.output {
+            Output::Window { images, .. } => images[0].dimensions(),
+            Output::Offscreen { image, .. } => image.dimensions(),
+        }
+    }
+
+    fn window_size_dependent_setup(&self) -> Result<Targets, Box<dyn Error>> {
+        let dimensions = self.dimensions();
+        let (depth_buffer, msaa_buffer) = if self.samples > 1 {
+            (AttachmentImage::transient_multisampled(self.device.clone(), dimensions, self.samples, Format::D16Unorm)?,
+             Some(AttachmentImage::transient_multisampled(self.device.clone(), dimensions, self.samples,
+                                                          self.format())?))
+        } else {
+            (AttachmentImage::transient(self.device.clone(), dimensions, Format::D16Unorm)?, None)
+        };
+
+        let framebuffers = match &self.output {
+            Output::Window { images, .. } => images.iter()
+                .map(|image| self.framebuffer(image.clone(), &depth_buffer, msaa_buffer.as_ref()))
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+            Output::Offscreen { image, .. } => vec![self.framebuffer(image.clone(), &depth_buffer, msaa_buffer.as_ref())?],
+        };
+
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+            depth_range: 0.0..1.0,
+        };
+        // Batches arrive bottom-first, so later draws at the same depth must win.
+        let depth_stencil = DepthStencil {
+            depth_compare: Compare::LessOrEqual,
+            ..DepthStencil::simple_depth_test()
+        };
+
+        // A macro rather than a closure, since each pair of shaders gives the builder a different type.
+        macro_rules! pipeline {
+            ($vs:expr, $fs:expr, $mode:expr) => {
+                Arc::new(GraphicsPipeline::start()
+                    .vertex_input(TwoBuffersDefinition::<VkVertex, VkColour>::new())
+                    .vertex_shader($vs.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_fixed_scissors_dynamic(iter::once(viewport.clone()))
+                    .fragment_shader($fs.main_entry_point(), ())
+                    .blend_collective(attachment_blend($mode))
+                    .depth_stencil(depth_stencil.clone())
+                    .render_pass(Subpass::from(self.render_pass.clone(), 0)
+                        .ok_or(SimpleError::new("Failed to load subpass"))?)
+                    .build(self.device.clone())?) as Pipeline
+            };
+        }
+
+        // Multiply and overlay read what is below from a backdrop, so they have shaders of their own.
+        let mut pipelines = HashMap::new();
+        let mut composite_pipelines = HashMap::new();
+        for &mode in BlendMode::all().iter() {
+            if mode.needs_backdrop() {
+                pipelines.insert(mode, pipeline!(self.vs, self.backdrop_fs, mode));
+                composite_pipelines.insert(mode, pipeline!(self.composite_vs, self.composite_backdrop_fs, mode));
+            } else {
+                pipelines.insert(mode, pipeline!(self.vs, self.fs, mode));
+                composite_pipelines.insert(mode, pipeline!(self.composite_vs, self.composite_fs, mode));
+            }
+        }
+
+        Ok(Targets { pipelines, composite_pipelines, framebuffers, depth_buffer, msaa_buffer, layers: Vec::new() })
+    }
+
+    /// Creates a framebuffer that ends up drawing to `image`.
+    fn framebuffer<I>(&self, image: I, depth_buffer: &Arc<AttachmentImage<Format>>,
+                      msaa_buffer: Option<&Arc<AttachmentImage<Format>>>)
+                      -> Result<Arc<dyn FramebufferAbstract + Send + Sync>, Box<dyn Error>>
+        where I: vulkano::image::ImageViewAccess + Send + Sync + 'static
+    {
+        let framebuffer = Framebuffer::start(self.render_pass.clone());
+        Ok(match msaa_buffer {
+            Some(msaa_buffer) => Arc::new(framebuffer
+                .add(msaa_buffer.clone())?
+                .add(depth_buffer.clone())?
+                .add(image)?
+                .build()?),
+            None => Arc::new(framebuffer
+                .add(image)?
                 .add(depth_buffer.clone())?
-                .build()?;
-            Ok(Arc::new(
-                buf
-            ) as Arc<dyn FramebufferAbstract + Send + Sync>)
-        }).collect::<Result<Vec<_>, Box<dyn Error>>>()?;
-
-        let pipeline = Arc::new(GraphicsPipeline::start()
-            .vertex_input(TwoBuffersDefinition::<VkVertex, VkColour>::new())
-            .vertex_shader(self.vs.main_entry_point(), ())
-            .triangle_list()
-            .viewports_dynamic_scissors_irrelevant(1)
-            .viewports(iter::once(Viewport {
-                origin: [0.0, 0.0],
-                dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-                depth_range: 0.0..1.0,
-            }))
-            .fragment_shader(self.fs.main_entry_point(), ())
-            .blend_alpha_blending()
-            .depth_stencil_simple_depth()
-            .render_pass(Subpass::from(self.render_pass.clone(), 0)
-                .ok_or(SimpleError::new("Failed to load subpass"))?)
-            .build(self.device.clone())?);
-
-        Ok((pipeline, framebuffers))
-    }
-
-    fn convert_vertex(&self, vert: Vertex) -> VkVertex {
+                .build()?),
+        })
+    }
+
+    /// Clear values for the attachments of the render pass, in order.
+    fn clear_values(&self, colour: [f32; 4]) -> Vec<ClearValue> {
+        if self.samples > 1 {
+            vec![colour.into(), 1f32.into(), ClearValue::None]
+        } else {
+            vec![colour.into(), 1f32.into()]
+        }
+    }
+
+    /// Records everything needed to draw `data` into framebuffer number `image_num`. Must be
+    /// called outside a render pass.
+    fn record_frame(&mut self, builder: AutoCommandBufferBuilder, data: &RenderData, targets: &mut Targets,
+                    image_num: usize, future: &mut Box<dyn GpuFuture>) -> Result<AutoCommandBufferBuilder, Box<dyn Error>> {
+        // A transparent window shows the desktop wherever nothing is drawn.
+        let clear_values = match &self.output {
+            Output::Window { options, .. } if options.transparent => self.clear_values([0., 0., 0., 0.]),
+            _ => self.clear_values([1., 0., 1., 1.]),
+        };
+        // Groups are drawn offscreen first, since that can't happen inside the main pass.
+        let mut layers = HashMap::new();
+        let builder = self.render_groups(builder, data, targets, &mut layers, future)?
+            .begin_render_pass(targets.framebuffers[image_num].clone(), false, clear_values)?;
+        let builder = self.draw(builder, data, targets, &layers, future)?
+            .end_render_pass()?;
+        // Layers that weren't needed this frame are freed.
+        targets.layers = layers.into_values().collect();
+        Ok(builder)
+    }
+
+    /// Renders every group in `data` (innermost first) into an offscreen image, recorded in
+    /// `layers`. Images are reused from `targets` while there are any. Must be called outside a
+    /// render pass.
+    fn render_groups(&mut self, mut builder: AutoCommandBufferBuilder, data: &RenderData, targets: &mut Targets,
+                     layers: &mut HashMap<*const Group, Layer>,
+                     future: &mut Box<dyn GpuFuture>) -> Result<AutoCommandBufferBuilder, Box<dyn Error>> {
+        for group in data.batches.iter().filter_map(|batch| batch.group.as_ref()) {
+            builder = self.render_groups(builder, &group.data, targets, layers, future)?;
+
+            let layer = match targets.layers.pop() {
+                Some(layer) => layer,
+                None => self.create_layer(targets)?,
+            };
+            builder = builder.begin_render_pass(layer.framebuffer.clone(), false, self.clear_values([0., 0., 0., 0.]))?;
+            builder = self.draw(builder, &group.data, targets, layers, future)?;
+            builder = builder.end_render_pass()?;
+            layers.insert(Arc::as_ptr(group), layer);
+        }
+        Ok(builder)
+    }
+
+    /// Creates an offscreen image the size of the frame to draw a group into.
+    fn create_layer(&self, targets: &Targets) -> Result<Layer, Box<dyn Error>> {
+        let image = AttachmentImage::with_usage(
+            self.device.clone(),
+            self.dimensions(),
+            self.format(),
+            ImageUsage { color_attachment: true, sampled: true, ..ImageUsage::none() })?;
+        let framebuffer = self.framebuffer(image.clone(), &targets.depth_buffer, targets.msaa_buffer.as_ref())?;
+        let set = Arc::new(PersistentDescriptorSet::start(targets.composite_pipelines[&BlendMode::Normal].clone(), 0)
+            .add_sampled_image(image.clone(), self.textures.sampler.clone())?
+            .build()?) as TextureSet;
+        Ok(Layer { framebuffer, set, image })
+    }
+
+    /// Records the draws for `data` into the current render pass. Groups must already have been
+    /// rendered into `layers`.
+    fn draw(&mut self, mut builder: AutoCommandBufferBuilder, data: &RenderData, targets: &Targets,
+            layers: &HashMap<*const Group, Layer>,
+            future: &mut Box<dyn GpuFuture>) -> Result<AutoCommandBufferBuilder, Box<dyn Error>> {
+        if data.indices.is_empty() {
+            return Ok(builder);
+        }
+
+        let vertices: Vec<VkVertex> = data.vertices.iter().zip(data.tex_coords.iter())
+            .map(|(vert, tex_coord)| self.convert_vertex(*vert, *tex_coord))
+            .collect();
+        let colours: Vec<VkColour> = data.colours.iter().map(|col| self.convert_colour(*col)).collect();
+
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), vertices.into_iter())?;
+        let colour_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), colours.into_iter())?;
+        let index_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), data.indices.iter().cloned())?;
+
+        for batch in data.batches.iter() {
+            let indices = index_buffer.clone().into_buffer_slice()
+                .slice(batch.start..batch.start + batch.count)
+                .ok_or(SimpleError::new("Batch out of range"))?;
+            let dynamic_state = DynamicState {
+                scissors: Some(vec![self.convert_clip(batch.clip)]),
+                ..DynamicState::none()
+            };
+            let buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> = vec!(vertex_buffer.clone(), colour_buffer.clone());
+            // Multiply and overlay read what is below from the layer of the batch's backdrop.
+            let backdrop = match &batch.backdrop {
+                Some(backdrop) if batch.blend.needs_backdrop() => Some(Arc::new(
+                    PersistentDescriptorSet::start(targets.pipelines[&batch.blend].clone(), 1)
+                        .add_sampled_image(layers.get(&Arc::as_ptr(backdrop))
+                                               .ok_or(SimpleError::new("Backdrop was not rendered"))?
+                                               .image.clone(),
+                                           self.textures.sampler.clone())?
+                        .build()?) as TextureSet),
+                _ => None,
+            };
+            builder = match (&batch.group, backdrop) {
+                (Some(group), backdrop) => {
+                    let set = layers.get(&Arc::as_ptr(group))
+                        .ok_or(SimpleError::new("Group was not rendered"))?
+                        .set
+                        .clone();
+                    match backdrop {
+                        Some(backdrop) => builder.draw_indexed(
+                            targets.composite_pipelines[&batch.blend].clone(), &dynamic_state, buffers,
+                            indices, (set, backdrop),
+                            composite_backdrop_fs::ty::Layer { opacity: group.opacity, mode: shader_mode(batch.blend) })?,
+                        // Without a backdrop, there is nothing to blend with, so it draws normally.
+                        None => builder.draw_indexed(
+                            targets.composite_pipelines[&without_backdrop(batch.blend)].clone(), &dynamic_state,
+                            buffers, indices, set, composite_fs::ty::Layer { opacity: group.opacity })?,
+                    }
+                },
+                (None, backdrop) => {
+                    let set = self.textures.get(batch.texture.as_ref(), &targets.pipelines[&BlendMode::Normal], future)?;
+                    match backdrop {
+                        Some(backdrop) => builder.draw_indexed(
+                            targets.pipelines[&batch.blend].clone(), &dynamic_state, buffers,
+                            indices, (set, backdrop), backdrop_fs::ty::Blend { mode: shader_mode(batch.blend) })?,
+                        None => builder.draw_indexed(
+                            targets.pipelines[&without_backdrop(batch.blend)].clone(), &dynamic_state, buffers,
+                            indices, set, ())?,
+                    }
+                },
+            };
+        }
+        Ok(builder)
+    }
+
+    fn convert_vertex(&self, vert: Vertex, tex_coord: TexCoord) -> VkVertex {
         let mut position = match vert {
             Vertex::Xy(x, y) => [x, y, 0.0],
             Vertex::Xyz(x, y, z) => [x, y, z]
@@ -97,50 +705,54 @@ impl VulkanBackend {
         position[0] *= 2.;
         position[1] *= 2.;
 
-        VkVertex { position }
+        VkVertex { position, tex_coord: [tex_coord.0, tex_coord.1] }
+    }
+
+    /// Premultiplies a colour, first decoding it to linear if the swapchain works in linear space.
+    fn convert_colour(&self, col: Colour) -> VkColour {
+        let col = if self.srgb { col.to_linear() } else { col };
+        let (r, g, b, a) = col.premultiplied().rgba();
+        VkColour { colour: [r, g, b, a] }
+    }
+
+    /// Converts a clip rectangle in logical coordinates to a scissor box in physical pixels.
+    fn convert_clip(&self, clip: Option<Rect>) -> Scissor {
+        let clip = match clip {
+            Some(clip) => clip,
+            None => return Scissor::irrelevant(),
+        };
+        let scale_x = self.phys_dims[0] as f32 / self.log_dims[0] as f32;
+        let scale_y = self.phys_dims[1] as f32 / self.log_dims[1] as f32;
+        let left = (clip.x * scale_x).round().max(0.);
+        let top = (clip.y * scale_y).round().max(0.);
+        let right = ((clip.x + clip.width) * scale_x).round().max(left);
+        let bottom = ((clip.y + clip.height) * scale_y).round().max(top);
+        Scissor {
+            origin: [left as i32, top as i32],
+            dimensions: [(right - left) as u32, (bottom - top) as u32],
+        }
     }
 }
 
-impl GfxProvider for VulkanBackend {
-    fn new() -> Result<Self, Box<dyn Error>> {
+impl VulkanBackend {
+    /// Opens a window, using the window options, device, present mode and multisampling in
+    /// `config`. A present mode the surface doesn't support falls back as described by
+    /// `PresentMode::fallbacks`.
+    pub fn with_config(config: &Config) -> Result<Self, Box<dyn Error>> {
         println!("Beginning Vulkan setup...");
         let instance = {
             let extensions = vulkano_win::required_extensions();
             Instance::new(None, &extensions, None)
         }?;
 
-        // We then choose which physical device to use.
-        //
-        // In a real application, there are three things to take into consideration:
-        //
-        // - Some devices may not support some of the optional features that may be required by your
-        //   application. You should filter out the devices that don't support your app.
-        //
-        // - Not all devices can draw to a certain surface. Once you create your window, you have to
-        //   choose a device that is capable of drawing to it.
-        //
-        // - You probably want to leave the choice between the remaining devices to the user.
-        //
-        let mut physical_devices = PhysicalDevice::enumerate(&instance);
-
-        for device in physical_devices.clone() {
-            println!("Found device: {} (type: {:?})", device.name(), device.ty());
-        }
-        let physical = physical_devices.next().ok_or(SimpleError::new("Found no devices"))?;
-        // Some debug info.
-        println!("Using {}.", physical.name());
-
-
         let events_loop = EventsLoop::new();
-        let surface = WindowBuilder::new()
-//            .with_transparency(true)
-            .with_decorations(false)
+        let surface = window_builder(&config.window, &events_loop)
             .build_vk_surface(&events_loop, instance.clone())?;
         let window = surface.window();
+        place_window(window, &config.window);
 
-        let queue_family = physical.queue_families().find(|&q| {
-            q.supports_graphics() && surface.is_supported(q).unwrap_or(false)
-        }).ok_or(SimpleError::new("Found no suitable devices"))?;
+        let (physical, queue_family) = choose_device(&instance, Some(&surface), &config.device)?;
+        println!("Using {}.", physical.name());
 
         let device_ext = DeviceExtensions { khr_swapchain: true, ..DeviceExtensions::none() };
         let (device, mut queues) = Device::new(physical, physical.supported_features(), &device_ext,
@@ -156,59 +768,59 @@ impl GfxProvider for VulkanBackend {
             return Err("Failed to load window dimensions".into());
         };
 
-        let (swapchain, images) = {
+        let ((swapchain, images), srgb) = {
             let caps = surface.capabilities(physical)?;
             let usage = caps.supported_usage_flags;
-            let alpha = caps.supported_composite_alpha.iter().next()
-                .ok_or(SimpleError::new("Found no transparency-supporting devices"))?;
-            let format = caps.supported_formats[0].0;
-
-            Swapchain::new(device.clone(), surface.clone(), caps.min_image_count, format,
-                           phys_dims, 1, usage, &queue, SurfaceTransform::Identity, alpha,
-                           PresentMode::Fifo, true, None)
-        }?;
+            // Colours are premultiplied, so a transparent window needs the compositor to expect that.
+            let alpha = if config.window.transparent && caps.supported_composite_alpha.pre_multiplied {
+                CompositeAlpha::PreMultiplied
+            } else {
+                caps.supported_composite_alpha.iter().next()
+                    .ok_or(SimpleError::new("Found no transparency-supporting devices"))?
+            };
+            // Prefer a format that the hardware encodes to sRGB, so blending happens in linear
+            // space. Otherwise, colours are drawn and blended as they are.
+            let (format, srgb) = caps.supported_formats.iter()
+                .find(|(format, space)| *space == ColorSpace::SrgbNonLinear
+                    && matches!(format, Format::B8G8R8A8Srgb | Format::R8G8B8A8Srgb))
+                .map(|(format, _)| (*format, true))
+                .unwrap_or_else(|| {
+                    println!("No sRGB swapchain format; blending in sRGB space.");
+                    (caps.supported_formats[0].0, false)
+                });
 
-        let vs = vs::Shader::load(device.clone())?;
-        let fs = fs::Shader::load(device.clone())?;
+            let convert = |mode| match mode {
+                PresentMode::Fifo => swapchain::PresentMode::Fifo,
+                PresentMode::FifoRelaxed => swapchain::PresentMode::Relaxed,
+                PresentMode::Mailbox => swapchain::PresentMode::Mailbox,
+                PresentMode::Immediate => swapchain::PresentMode::Immediate,
+            };
+            let present_mode = config.present_mode.fallbacks().iter()
+                .copied()
+                .find(|&mode| caps.present_modes.supports(convert(mode)))
+                .unwrap_or(PresentMode::Fifo);
+            if present_mode != config.present_mode {
+                println!("{:?} presentation is not supported; using {:?}.", config.present_mode, present_mode);
+            }
+            let present_mode = convert(present_mode);
 
-        let render_pass = Arc::new(vulkano::single_pass_renderpass!(device.clone(),
-                attachments: {
-                    color: {
-                        load: Clear,
-                        store: Store,
-                        format: swapchain.format(),
-                        samples: 1,
-                    },
-                    depth: {
-                        load: Clear,
-                        store: DontCare,
-                        format: Format::D16Unorm,
-                        samples: 1,
-                    }
-                },
-                pass: {
-                    color: [color],
-                    depth_stencil: {depth}
-                }
-            )?);
+            (Swapchain::new(device.clone(), surface.clone(), caps.min_image_count, format,
+                            phys_dims, 1, usage, &queue, SurfaceTransform::Identity, alpha,
+                            present_mode, true, None)?, srgb)
+        };
 
-        let show_fps = false;
-        let images = images.to_vec();
+        let output = Output::Window {
+            surface, swapchain, images: images.to_vec(), events_loop, options: config.window.clone(),
+        };
+        let mut backend = Self::with_output(device, queue, output, srgb, phys_dims, log_dims)?.msaa(config.samples);
+        backend.screenshot_key = config.screenshot_key.clone();
+        Ok(backend)
+    }
+}
 
-        Ok(Self {
-            show_fps,
-            device,
-            vs,
-            fs,
-            images,
-            render_pass,
-            swapchain,
-            surface,
-            queue,
-            events_loop,
-            phys_dims,
-            log_dims
-        })
+impl GfxProvider for VulkanBackend {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Self::with_config(&Config::new())
     }
 
     fn show_fps(mut self) -> Self {
@@ -216,17 +828,35 @@ impl GfxProvider for VulkanBackend {
         self
     }
 
+    fn max_fps(mut self, fps: Option<u32>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    /// Draws until the window is closed. A headless backend has nowhere to show frames, so it
+    /// draws one and returns.
     fn run(mut self, mut vertex_producer: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>> {
-        let (mut pipeline, mut framebuffers) = self.window_size_dependent_setup()?;
+        let (surface, mut swapchain) = match &self.output {
+            Output::Window { surface, swapchain, .. } => (surface.clone(), swapchain.clone()),
+            Output::Offscreen { .. } => return self.render(vertex_producer.as_mut()),
+        };
+        if self.samples > 1 {
+            self.render_pass = create_render_pass(self.device.clone(), swapchain.format(), self.samples)?;
+        }
+        let mut targets = self.window_size_dependent_setup()?;
         let mut recreate_swapchain = false;
-        let window = self.surface.window();
+        let mut take_screenshot = false;
+        let mut input = WinitInput::default();
+        let window = surface.window();
 
         let mut previous_frame_end = Box::new(sync::now(self.device.clone())) as Box<dyn GpuFuture>;
 
+        let mut limiter = FrameLimiter::new(self.max_fps);
         let mut t0 = Instant::now();
         let mut updates = 0;
         let fps_freq = 100;
         loop {
+            limiter.wait();
             if self.show_fps {
                 // The below line panics on my Intel Ultra HD 620 setup,
                 // but only on debug. It seems to be a bug in Vulkano, specifically
@@ -257,23 +887,25 @@ impl GfxProvider for VulkanBackend {
                 self.phys_dims = phys_dims;
                 self.log_dims = log_dims;
 
-                let (new_swapchain, new_images) = match self.swapchain.recreate_with_dimension(phys_dims) {
+                let (new_swapchain, new_images) = match swapchain.recreate_with_dimension(phys_dims) {
                     Ok(r) => r,
                     Err(SwapchainCreationError::UnsupportedDimensions) => continue,
                     Err(err) => panic!("{:?}", err)
                 };
 
-                self.swapchain = new_swapchain;
-                self.images = new_images.to_vec();
+                swapchain = new_swapchain.clone();
+                if let Output::Window { swapchain, images, .. } = &mut self.output {
+                    *swapchain = new_swapchain;
+                    *images = new_images.to_vec();
+                }
 
-                let (new_pipeline, new_framebuffers) = self.window_size_dependent_setup()?;
-                pipeline = new_pipeline;
-                framebuffers = new_framebuffers;
+                targets = self.window_size_dependent_setup()?;
+                self.textures.invalidate_sets();
 
                 recreate_swapchain = false;
             }
 
-            let (image_num, acquire_future) = match swapchain::acquire_next_image(self.swapchain.clone(), None) {
+            let (image_num, acquire_future) = match swapchain::acquire_next_image(swapchain.clone(), None) {
                 Ok(r) => r,
                 Err(AcquireError::OutOfDate) => {
                     recreate_swapchain = true;
@@ -282,37 +914,51 @@ impl GfxProvider for VulkanBackend {
                 Err(err) => panic!("{:?}", err)
             };
 
-            let clear_values = vec![[1., 0., 1., 1.].into(), 1f32.into()];
+            if let (Some(new), Output::Window { options, .. }) = (vertex_producer.window_options(), &mut self.output) {
+                update_window(window, options, &new);
+                *options = new;
+            }
 
-            let (vertices, colours, indices) = vertex_producer.get_data(RuntimeParams {
+            let data = vertex_producer.get_data(RuntimeParams {
                 window_width: self.log_dims[0] as u16,
                 window_height: self.log_dims[1] as u16
             });
 
-            let vertices: Vec<VkVertex> = vertices.into_iter().map(|vert| self.convert_vertex(vert)).collect();
-            let colours: Vec<VkColour> = colours.into_iter().map(|col| VkColour::from(col)).collect();
-
-            let vertex_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), vertices.iter().cloned())?;
-            let colour_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), colours.iter().cloned())?;
-            let index_buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), BufferUsage::all(), indices.iter().cloned())?;
-
-            let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())?
-                .begin_render_pass(framebuffers[image_num].clone(), false, clear_values)?
-                .draw_indexed(
-                    pipeline.clone(),
-                    &DynamicState::none(),
-                    vec!(vertex_buffer.clone(), colour_buffer.clone()),
-                    index_buffer.clone(), (), ())?
-                .end_render_pass()?
-                .build()?;
+            let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())?;
+            let mut command_buffer = self.record_frame(command_buffer, &data.with_backdrops(), &mut targets, image_num,
+                                                       &mut previous_frame_end)?;
+            // The frame is copied out before it is presented.
+            let mut screenshot = None;
+            if take_screenshot {
+                if let Output::Window { images, .. } = &self.output {
+                    match self.screenshot_buffer() {
+                        Ok(buffer) => {
+                            command_buffer = command_buffer.copy_image_to_buffer(images[image_num].clone(), buffer.clone())?;
+                            screenshot = Some(buffer);
+                        },
+                        Err(err) => println!("Failed to save screenshot: {}", err),
+                    }
+                }
+                take_screenshot = false;
+            }
+            let command_buffer = command_buffer.build()?;
+            self.textures.end_frame();
 
             let future = previous_frame_end.join(acquire_future)
                 .then_execute(self.queue.clone(), command_buffer)?
-                .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num)
+                .then_swapchain_present(self.queue.clone(), swapchain.clone(), image_num)
                 .then_signal_fence_and_flush();
 
             match future {
                 Ok(future) => {
+                    if let (Some(buffer), Some((_, directory))) = (screenshot, &self.screenshot_key) {
+                        let saved = future.wait(None).map_err(|err| err.into())
+                            .and_then(|_| self.save_screenshot(&buffer, directory));
+                        match saved {
+                            Ok(path) => println!("Saved screenshot to {}.", path.display()),
+                            Err(err) => println!("Failed to save screenshot: {}", err),
+                        }
+                    }
                     previous_frame_end = Box::new(future) as Box<_>;
                 }
                 Err(FlushError::OutOfDate) => {
@@ -326,13 +972,20 @@ impl GfxProvider for VulkanBackend {
             }
 
             let mut done = false;
-            self.events_loop.poll_events(|ev| {
-                match ev {
-                    Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
-                    Event::WindowEvent { event: WindowEvent::Resized(_), .. } => recreate_swapchain = true,
-                    _ => ()
-                }
-            });
+            let screenshot_key = self.screenshot_key.as_ref().map(|(key, _)| *key);
+            if let Output::Window { events_loop, .. } = &mut self.output {
+                events_loop.poll_events(|ev| {
+                    match ev {
+                        Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+                        Event::WindowEvent { event: WindowEvent::Resized(_), .. } => recreate_swapchain = true,
+                        Event::WindowEvent { event, .. } => if let Some(event) = input.convert(&event) {
+                            take_screenshot |= matches!(event, InputEvent::Key { key, .. } if Some(key) == screenshot_key);
+                            vertex_producer.handle_event(event);
+                        },
+                        _ => ()
+                    }
+                });
+            }
 
             if done {
                 return Ok(());
@@ -341,27 +994,91 @@ impl GfxProvider for VulkanBackend {
     }
 }
 
+/// Chooses a device, and a queue family on it that can draw to `surface` if there is one,
+/// following `policy`.
+fn choose_device<'a>(instance: &'a Arc<Instance>, surface: Option<&Surface<Window>>, policy: &DevicePolicy)
+        -> Result<(PhysicalDevice<'a>, QueueFamily<'a>), Box<dyn Error>> {
+    let mut candidates = Vec::new();
+    let mut skipped = Vec::new();
+    for physical in PhysicalDevice::enumerate(instance) {
+        let name = physical.name();
+        let check = || {
+            if !policy.matches_name(&name) {
+                return Err("its name doesn't match");
+            }
+            if !physical.supported_features().superset_of(&policy.features) {
+                return Err("it lacks required features");
+            }
+            let presents = |q| match surface {
+                Some(surface) => surface.is_supported(q).unwrap_or(false),
+                None => true,
+            };
+            physical.queue_families()
+                .find(|&q| q.supports_graphics() && presents(q))
+                .ok_or(if surface.is_some() { "it can't draw to the window" } else { "it can't draw" })
+        };
+        match check() {
+            Ok(queue_family) => candidates.push((physical, queue_family)),
+            Err(reason) => skipped.push(format!("skipped {} because {}", name, reason)),
+        }
+    }
+
+    let devices: Vec<DeviceInfo> = candidates.iter()
+        .map(|(physical, _)| DeviceInfo { index: physical.index(), name: physical.name(), kind: device_kind(physical.ty()) })
+        .collect();
+    match policy.pick(&devices) {
+        Some(i) => Ok(candidates[i]),
+        None if skipped.is_empty() => Err(SimpleError::new("Found no devices").into()),
+        None => Err(SimpleError::new(format!("Found no suitable devices ({})", skipped.join("; "))).into()),
+    }
+}
+
+fn device_kind(ty: PhysicalDeviceType) -> DeviceKind {
+    match ty {
+        PhysicalDeviceType::DiscreteGpu => DeviceKind::Discrete,
+        PhysicalDeviceType::IntegratedGpu => DeviceKind::Integrated,
+        PhysicalDeviceType::VirtualGpu => DeviceKind::Virtual,
+        PhysicalDeviceType::Cpu => DeviceKind::Cpu,
+        PhysicalDeviceType::Other => DeviceKind::Other,
+    }
+}
+
+/// Multiply and overlay pipelines need a backdrop, so a batch without one is drawn normally.
+fn without_backdrop(mode: BlendMode) -> BlendMode {
+    if mode.needs_backdrop() { BlendMode::Normal } else { mode }
+}
+
+/// The `mode` push constant of the backdrop shaders.
+fn shader_mode(mode: BlendMode) -> i32 {
+    if mode == BlendMode::Multiply { 1 } else { 2 }
+}
+
 #[derive(Default, Debug, Clone)]
-struct VkVertex { position: [f32; 3] }
-vulkano::impl_vertex!(VkVertex, position);
+struct VkVertex { position: [f32; 3], tex_coord: [f32; 2] }
+vulkano::impl_vertex!(VkVertex, position, tex_coord);
 
 
 #[derive(Default, Debug, Clone)]
 struct VkColour { colour: [f32; 4] }
 vulkano::impl_vertex!(VkColour, colour);
 
-impl From<Colour> for VkColour {
-    fn from(col: Colour) -> Self {
-        let mut colour = match col {
-            Colour::Rgb(r, g, b) => [r, g, b, 1.0],
-            Colour::Rgba(r, g, b, a) => [r, g, b, a]
-        };
-        // Convert from sRGB; the Vulkano API doesn't allow us to change the colour space
-        for i in colour.iter_mut() {
-            *i = i.powf(2.2);
-        }
-
-        Self { colour }
+/// Everything is drawn with premultiplied alpha: vertex colours are premultiplied on the CPU and
+/// textures in the fragment shader, and offscreen layers hold premultiplied colours already.
+/// Alpha always accumulates as it does for normal blending.
+fn attachment_blend(mode: BlendMode) -> AttachmentBlend {
+    let (color_source, color_destination) = match mode {
+        BlendMode::Normal => (BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+        BlendMode::Screen => (BlendFactor::One, BlendFactor::OneMinusSrcColor),
+        BlendMode::Additive => (BlendFactor::One, BlendFactor::One),
+        // The shader does the blending, and only needs what is below added back.
+        BlendMode::Multiply | BlendMode::Overlay => (BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+    };
+    AttachmentBlend {
+        color_source,
+        color_destination,
+        alpha_source: BlendFactor::One,
+        alpha_destination: BlendFactor::OneMinusSrcAlpha,
+        ..AttachmentBlend::alpha_blending()
     }
 }
 
@@ -372,13 +1089,16 @@ mod vs {
                 #version 450
 
                 layout(location = 0) in vec3 position;
-                layout(location = 1) in vec4 colour;
+                layout(location = 1) in vec2 tex_coord;
+                layout(location = 2) in vec4 colour;
 
                 layout(location = 0) out vec4 fragColour;
+                layout(location = 1) out vec2 fragTexCoord;
 
                 void main() {
                     gl_Position = vec4(position, 1.0);
                     fragColour = colour;
+                    fragTexCoord = tex_coord;
                 }
         "
     }
@@ -391,10 +1111,136 @@ mod fs {
                 #version 450
 
                 layout(location = 0) in vec4 fragColour;
+                layout(location = 1) in vec2 fragTexCoord;
+                layout(location = 0) out vec4 f_color;
+
+                layout(set = 0, binding = 0) uniform sampler2D tex;
+
+                void main() {
+                    // Premultiply here rather than on upload, so that textures decode from sRGB
+                    // before being multiplied.
+                    vec4 texel = texture(tex, fragTexCoord);
+                    f_color = fragColour * vec4(texel.rgb * texel.a, texel.a);
+                }
+        "
+    }
+}
+
+/// Like `fs`, but blends the result with the backdrop, which is read at the same place on screen.
+mod backdrop_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 450
+
+                layout(location = 0) in vec4 fragColour;
+                layout(location = 1) in vec2 fragTexCoord;
+                layout(location = 0) out vec4 f_color;
+
+                layout(set = 0, binding = 0) uniform sampler2D tex;
+                layout(set = 1, binding = 0) uniform sampler2D backdrop;
+                // 1 multiplies with the backdrop and 2 overlays it.
+                layout(push_constant) uniform Blend {
+                    int mode;
+                } blend_mode;
+
+                // Blends the premultiplied `source` with `below` following the W3C compositing
+                // spec, leaving out the share of `below` that blending then adds.
+                vec4 blend(vec4 source, vec4 below, int mode) {
+                    vec3 cs = source.rgb / max(source.a, 1e-6);
+                    vec3 cb = below.rgb / max(below.a, 1e-6);
+                    vec3 blended = mode == 1
+                        ? cs * cb
+                        : mix(2.0 * cs * cb, 1.0 - 2.0 * (1.0 - cs) * (1.0 - cb), step(0.5, cb));
+                    return vec4(source.rgb * (1.0 - below.a) + source.a * below.a * blended, source.a);
+                }
+
+                void main() {
+                    vec4 texel = texture(tex, fragTexCoord);
+                    vec4 source = fragColour * vec4(texel.rgb * texel.a, texel.a);
+                    f_color = blend(source, texelFetch(backdrop, ivec2(gl_FragCoord.xy), 0), blend_mode.mode);
+                }
+        "
+    }
+}
+
+/// Like `vs`, but samples the layer at the same place on screen that it is drawn.
+mod composite_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 450
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec2 tex_coord;
+                layout(location = 2) in vec4 colour;
+
+                layout(location = 0) out vec4 fragColour;
+                layout(location = 1) out vec2 fragTexCoord;
+
+                void main() {
+                    gl_Position = vec4(position, 1.0);
+                    fragColour = colour;
+                    fragTexCoord = position.xy * 0.5 + 0.5;
+                }
+        "
+    }
+}
+
+mod composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 450
+
+                layout(location = 0) in vec4 fragColour;
+                layout(location = 1) in vec2 fragTexCoord;
                 layout(location = 0) out vec4 f_color;
 
+                layout(set = 0, binding = 0) uniform sampler2D tex;
+                layout(push_constant) uniform Layer {
+                    float opacity;
+                } layer;
+
+                void main() {
+                    // The layer is already premultiplied, so fading it scales every channel.
+                    f_color = layer.opacity * fragColour * texture(tex, fragTexCoord);
+                }
+        "
+    }
+}
+
+/// Like `composite_fs`, but blends the layer with the backdrop, as `backdrop_fs` does.
+mod composite_backdrop_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 450
+
+                layout(location = 0) in vec4 fragColour;
+                layout(location = 1) in vec2 fragTexCoord;
+                layout(location = 0) out vec4 f_color;
+
+                layout(set = 0, binding = 0) uniform sampler2D tex;
+                layout(set = 1, binding = 0) uniform sampler2D backdrop;
+                layout(push_constant) uniform Layer {
+                    float opacity;
+                    int mode;
+                } layer;
+
+                // The same as in `backdrop_fs`.
+                vec4 blend(vec4 source, vec4 below, int mode) {
+                    vec3 cs = source.rgb / max(source.a, 1e-6);
+                    vec3 cb = below.rgb / max(below.a, 1e-6);
+                    vec3 blended = mode == 1
+                        ? cs * cb
+                        : mix(2.0 * cs * cb, 1.0 - 2.0 * (1.0 - cs) * (1.0 - cb), step(0.5, cb));
+                    return vec4(source.rgb * (1.0 - below.a) + source.a * below.a * blended, source.a);
+                }
+
                 void main() {
-                    f_color = vec4(fragColour);
+                    vec4 source = layer.opacity * fragColour * texture(tex, fragTexCoord);
+                    f_color = blend(source, texelFetch(backdrop, ivec2(gl_FragCoord.xy), 0), layer.mode);
                 }
         "
     }
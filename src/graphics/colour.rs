@@ -0,0 +1,311 @@
+use std::fmt;
+use std::str::FromStr;
+
+use simple_error::SimpleError;
+
+use crate::graphics::Colour;
+
+impl Colour {
+    /// The components as (red, green, blue, alpha), each from 0 to 1.
+    pub fn rgba(&self) -> (f32, f32, f32, f32) {
+        match *self {
+            Colour::Rgb(r, g, b) => (r, g, b, 1.),
+            Colour::Rgba(r, g, b, a) => (r, g, b, a),
+        }
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.rgba().3
+    }
+
+    pub fn with_alpha(&self, alpha: f32) -> Self {
+        let (r, g, b, _) = self.rgba();
+        Colour::Rgba(r, g, b, alpha)
+    }
+
+    /// Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`. The `#` is optional.
+    pub fn from_hex(hex: &str) -> Result<Self, SimpleError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let invalid = || SimpleError::new(format!("Invalid hex colour: {}", hex));
+        // `from_str_radix` alone would also take a leading sign.
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |i: usize, len: usize| -> Result<u8, SimpleError> {
+            let value = u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).map_err(|_| invalid())?;
+            // A single digit is repeated, so "f" means "ff".
+            Ok(if len == 1 { value * 17 } else { value })
+        };
+        match digits.len() {
+            3 => Ok(Colour::rgb8(channel(0, 1)?, channel(1, 1)?, channel(2, 1)?)),
+            4 => Ok(Colour::rgba8(channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, channel(3, 1)?)),
+            6 => Ok(Colour::rgb8(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+            8 => Ok(Colour::rgba8(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, channel(3, 2)?)),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Looks up a CSS colour name, ignoring case.
+    pub fn named(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Colour::Rgba(0., 0., 0., 0.));
+        }
+        NAMED_COLOURS.iter()
+            .find(|(named, _)| *named == name)
+            .map(|(_, rgb)| Colour::rgb8((rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8))
+    }
+
+    /// Hue is in degrees; saturation and lightness are from 0 to 1.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        Self::from_hsla(hue, saturation, lightness, 1.)
+    }
+
+    pub fn from_hsla(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Self {
+        let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+        let (r, g, b) = from_hue(hue, chroma, lightness - chroma / 2.);
+        Colour::Rgba(r, g, b, alpha)
+    }
+
+    /// Hue is in degrees; saturation and value are from 0 to 1.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        Self::from_hsva(hue, saturation, value, 1.)
+    }
+
+    pub fn from_hsva(hue: f32, saturation: f32, value: f32, alpha: f32) -> Self {
+        let chroma = value * saturation;
+        let (r, g, b) = from_hue(hue, chroma, value - chroma);
+        Colour::Rgba(r, g, b, alpha)
+    }
+
+    /// The colour as (hue, saturation, lightness), ignoring alpha.
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (r, g, b, _) = self.rgba();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.;
+        let saturation = if max == min {
+            0.
+        } else {
+            (max - min) / (1. - (2. * lightness - 1.).abs())
+        };
+        (hue(r, g, b), saturation, lightness)
+    }
+
+    /// The colour as (hue, saturation, value), ignoring alpha.
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (r, g, b, _) = self.rgba();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let saturation = if max == 0. { 0. } else { (max - min) / max };
+        (hue(r, g, b), saturation, max)
+    }
+
+    /// Adds `amount` to the HSL lightness, so 0.1 makes the colour 10% lighter.
+    pub fn lighten(&self, amount: f32) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self::from_hsla(h, s, (l + amount).clamp(0., 1.), self.alpha())
+    }
+
+    pub fn darken(&self, amount: f32) -> Self {
+        self.lighten(-amount)
+    }
+
+    /// Blends towards `other`: a `t` of 0 gives this colour, and 1 gives `other`.
+    pub fn mix(&self, other: &Colour, t: f32) -> Self {
+        let (r1, g1, b1, a1) = self.rgba();
+        let (r2, g2, b2, a2) = other.rgba();
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Colour::Rgba(lerp(r1, r2), lerp(g1, g2), lerp(b1, b2), lerp(a1, a2))
+    }
+
+    /// Decodes sRGB components to linear light. Alpha is already linear, so it is left alone.
+    pub fn to_linear(&self) -> Self {
+        let (r, g, b, a) = self.rgba();
+        Colour::Rgba(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    /// The inverse of `to_linear`.
+    pub fn to_srgb(&self) -> Self {
+        let (r, g, b, a) = self.rgba();
+        Colour::Rgba(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a)
+    }
+
+    /// Multiplies red, green and blue by alpha.
+    pub fn premultiplied(&self) -> Self {
+        let (r, g, b, a) = self.rgba();
+        Colour::Rgba(r * a, g * a, b * a, a)
+    }
+}
+
+/// The sRGB transfer function, as specified rather than approximated by a 2.2 power.
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// The red, green and blue for a hue in degrees, given the chroma and the amount to add to each.
+fn from_hue(hue: f32, chroma: f32, offset: f32) -> (f32, f32, f32) {
+    let sector = hue.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    (r + offset, g + offset, b + offset)
+}
+
+/// The hue in degrees shared by HSL and HSV; 0 for greys.
+fn hue(r: f32, g: f32, b: f32) -> f32 {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0. {
+        return 0.;
+    }
+    let hue = if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.
+    } else {
+        (r - g) / delta + 4.
+    };
+    (hue * 60.).rem_euclid(360.)
+}
+
+/// Parses a hex colour (starting with `#`) or a CSS colour name.
+impl FromStr for Colour {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with('#') {
+            Self::from_hex(s)
+        } else {
+            Self::named(s).ok_or_else(|| SimpleError::new(format!("Unknown colour: {}", s)))
+        }
+    }
+}
+
+/// Formats as `#rrggbb`, or `#rrggbbaa` if the colour isn't opaque.
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (r, g, b, a) = self.rgba();
+        let byte = |x: f32| (x.clamp(0., 1.) * 255.).round() as u8;
+        write!(f, "#{:02x}{:02x}{:02x}", byte(r), byte(g), byte(b))?;
+        if byte(a) != 255 {
+            write!(f, "{:02x}", byte(a))?;
+        }
+        Ok(())
+    }
+}
+
+/// The CSS named colours, as 0xRRGGBB.
+const NAMED_COLOURS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff), ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff), ("beige", 0xf5f5dc), ("bisque", 0xffe4c4), ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd), ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00), ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed), ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff), ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9), ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f), ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000), ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1), ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff), ("dimgray", 0x696969), ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff), ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff), ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520), ("gray", 0x808080), ("green", 0x008000), ("greenyellow", 0xadff2f),
+    ("grey", 0x808080), ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c), ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00), ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080), ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899), ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000), ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3), ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee), ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1), ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead), ("navy", 0x000080), ("oldlace", 0xfdf5e6), ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23), ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee), ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9), ("peru", 0xcd853f), ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd), ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1), ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072), ("sandybrown", 0xf4a460), ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d), ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa), ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080), ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347), ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+    ("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(colour: Colour) -> [u8; 4] {
+        let (r, g, b, a) = colour.rgba();
+        let byte = |x: f32| (x * 255.).round() as u8;
+        [byte(r), byte(g), byte(b), byte(a)]
+    }
+
+    #[test]
+    fn parses_every_hex_form() {
+        assert_eq!(bytes(Colour::from_hex("#f80").unwrap()), [255, 136, 0, 255]);
+        assert_eq!(bytes(Colour::from_hex("f808").unwrap()), [255, 136, 0, 136]);
+        assert_eq!(bytes(Colour::from_hex("#1a2B3c").unwrap()), [26, 43, 60, 255]);
+        assert_eq!(bytes(Colour::from_hex("#1a2b3c80").unwrap()), [26, 43, 60, 128]);
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        for hex in &["", "#", "#12", "#12345", "#1234567", "#123456789", "#gg0000", "#+12", "#+f+f+f", "#-1-1-1", "#é12"] {
+            assert!(Colour::from_hex(hex).is_err(), "{} should be invalid", hex);
+        }
+    }
+
+    #[test]
+    fn looks_up_named_colours() {
+        assert_eq!(bytes(Colour::named("RebeccaPurple").unwrap()), [102, 51, 153, 255]);
+        assert_eq!(bytes(Colour::named("transparent").unwrap()), [0, 0, 0, 0]);
+        assert!(Colour::named("notacolour").is_none());
+        assert!(NAMED_COLOURS.iter().all(|(name, _)| Colour::named(name).is_some()));
+    }
+
+    #[test]
+    fn hsl_round_trips() {
+        for &(r, g, b) in &[(255, 0, 0), (12, 200, 99), (128, 128, 128), (250, 240, 10), (0, 0, 0)] {
+            let colour = Colour::rgb8(r, g, b);
+            let (h, s, l) = colour.to_hsl();
+            assert_eq!(bytes(Colour::from_hsl(h, s, l)), [r, g, b, 255]);
+        }
+        assert_eq!(bytes(Colour::from_hsl(120., 1., 0.5)), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn display_round_trips_through_from_str() {
+        for text in &["#000000", "#ff8800", "#1a2b3c80", "#ffffff00"] {
+            let colour: Colour = text.parse().unwrap();
+            assert_eq!(colour.to_string(), *text);
+        }
+        assert_eq!(" red ".parse::<Colour>().unwrap().to_string(), "#ff0000");
+        assert!("#xyz".parse::<Colour>().is_err());
+        assert!("nope".parse::<Colour>().is_err());
+    }
+}
@@ -0,0 +1,463 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use simple_error::SimpleError;
+
+#[cfg(feature = "vulkan")]
+use vulkano::device::Features;
+
+use crate::graphics::GfxProvider;
+use crate::graphics::backends::Backend;
+#[cfg(feature = "opengl")]
+use crate::graphics::backends::gl::GlBackend;
+use crate::graphics::backends::software::SoftwareBackend;
+#[cfg(feature = "terminal")]
+use crate::graphics::backends::terminal::TerminalBackend;
+#[cfg(feature = "vulkan")]
+use crate::graphics::backends::vk::VulkanBackend;
+use crate::graphics::input::Key;
+use crate::graphics::window::WindowOptions;
+
+/// The environment variable that overrides which backends to try, e.g. `FLUID_BACKEND=software`.
+/// It takes a backend name, or several separated by commas to try in order.
+pub const BACKEND_VAR: &str = "FLUID_BACKEND";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    /// Only available with the `vulkan` feature.
+    Vulkan,
+    /// Only available with the `opengl` feature.
+    OpenGl,
+    /// Draws on the CPU without a window; see `SoftwareBackend`.
+    Software,
+    /// Only available with the `terminal` feature.
+    Terminal,
+}
+
+impl BackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Vulkan => "vulkan",
+            BackendKind::OpenGl => "opengl",
+            BackendKind::Software => "software",
+            BackendKind::Terminal => "terminal",
+        }
+    }
+
+    /// Whether this backend was built in, i.e. its feature is enabled.
+    pub fn is_built(&self) -> bool {
+        match self {
+            BackendKind::Vulkan => cfg!(feature = "vulkan"),
+            BackendKind::OpenGl => cfg!(feature = "opengl"),
+            BackendKind::Software => true,
+            BackendKind::Terminal => cfg!(feature = "terminal"),
+        }
+    }
+}
+
+/// Parses a backend name, ignoring case. Vulkan and OpenGL can also be called `vk` and `gl`.
+impl FromStr for BackendKind {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "vulkan" | "vk" => Ok(BackendKind::Vulkan),
+            "opengl" | "gl" => Ok(BackendKind::OpenGl),
+            "software" => Ok(BackendKind::Software),
+            "terminal" => Ok(BackendKind::Terminal),
+            _ => Err(SimpleError::new(format!("Unknown backend: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How finished frames are shown on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PresentMode {
+    /// Waits for vertical blank, so there is no tearing.
+    Fifo,
+    /// Like `Fifo`, but a frame that misses vertical blank is shown straight away, which may
+    /// tear. OpenGL draws as it would with `Fifo`.
+    FifoRelaxed,
+    /// Waits for vertical blank, but replaces the waiting frame rather than queueing behind it.
+    /// OpenGL can't do this, and draws as it would with `Immediate`.
+    Mailbox,
+    /// Shows each frame straight away, which may tear.
+    Immediate,
+}
+
+impl Default for PresentMode {
+    fn default() -> Self {
+        PresentMode::Fifo
+    }
+}
+
+impl PresentMode {
+    /// This mode, followed by the ones to use in order if it isn't supported. Every device
+    /// supports `Fifo`, so it always comes last. `Mailbox` and `Immediate` fall back to each
+    /// other first, since both draw without waiting.
+    pub fn fallbacks(&self) -> &'static [PresentMode] {
+        match self {
+            PresentMode::Fifo => &[PresentMode::Fifo],
+            PresentMode::FifoRelaxed => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+            PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate, PresentMode::Fifo],
+            PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo],
+        }
+    }
+}
+
+/// Which kind of GPU to use when there is more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DevicePreference {
+    /// The first device the driver lists.
+    Any,
+    /// A discrete GPU, if there is one, for performance.
+    Discrete,
+    /// A GPU built into the CPU, if there is one, to save power.
+    Integrated,
+}
+
+impl Default for DevicePreference {
+    fn default() -> Self {
+        DevicePreference::Any
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    Discrete,
+    Integrated,
+    Virtual,
+    /// Drawing in software, e.g. lavapipe.
+    Cpu,
+    Other,
+}
+
+/// A device that could be chosen, as shown to `DevicePolicy::choose`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Where the driver lists it, counting from 0.
+    pub index: usize,
+    pub name: String,
+    pub kind: DeviceKind,
+}
+
+type ChooseDevice = Arc<dyn Fn(&[DeviceInfo]) -> Option<usize> + Send + Sync>;
+
+/// How to choose a GPU. Only the Vulkan backend can choose, for a window or headless; OpenGL
+/// gets whichever device the driver gives it.
+///
+/// Devices that can't draw to the window (or at all, when headless), whose name doesn't match, or that lack a required
+/// feature are left out. Of the rest, `choose` picks one if it is set, and otherwise the first
+/// of the preferred kind is used, or the first of any kind if there is none.
+#[derive(Clone)]
+pub struct DevicePolicy {
+    pub preference: DevicePreference,
+    /// Only use devices whose name contains this, ignoring case.
+    pub name: Option<String>,
+    /// Only use devices that support all of these.
+    #[cfg(feature = "vulkan")]
+    pub features: Features,
+    pub choose: Option<ChooseDevice>,
+}
+
+impl DevicePolicy {
+    /// Any device that can draw to the window.
+    pub fn new() -> Self {
+        Self {
+            preference: DevicePreference::Any,
+            name: None,
+            #[cfg(feature = "vulkan")]
+            features: Features::none(),
+            choose: None,
+        }
+    }
+
+    pub fn prefer(mut self, preference: DevicePreference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    #[cfg(feature = "vulkan")]
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Chooses with `choose`, which gets the devices that are left after the other checks and
+    /// returns the position of one of them. Returning `None` falls back to the preference.
+    pub fn choose<F>(mut self, choose: F) -> Self
+            where F: Fn(&[DeviceInfo]) -> Option<usize> + Send + Sync + 'static {
+        self.choose = Some(Arc::new(choose));
+        self
+    }
+
+    /// Whether a device passes the name check.
+    #[cfg(feature = "vulkan")]
+    pub(crate) fn matches_name(&self, name: &str) -> bool {
+        match &self.name {
+            Some(wanted) => name.to_lowercase().contains(&wanted.to_lowercase()),
+            None => true,
+        }
+    }
+
+    /// Picks one of `devices`, which have already passed every check, returning its position.
+    #[cfg(feature = "vulkan")]
+    pub(crate) fn pick(&self, devices: &[DeviceInfo]) -> Option<usize> {
+        if devices.is_empty() {
+            return None;
+        }
+        if let Some(i) = self.choose.as_ref().and_then(|choose| choose(devices)) {
+            if i < devices.len() {
+                return Some(i);
+            }
+        }
+        let preferred = match self.preference {
+            DevicePreference::Any => None,
+            DevicePreference::Discrete => Some(DeviceKind::Discrete),
+            DevicePreference::Integrated => Some(DeviceKind::Integrated),
+        };
+        devices.iter().position(|device| Some(device.kind) == preferred).or(Some(0))
+    }
+}
+
+impl fmt::Debug for DevicePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("DevicePolicy");
+        debug.field("preference", &self.preference).field("name", &self.name);
+        #[cfg(feature = "vulkan")]
+        debug.field("features", &self.features);
+        debug.field("choose", &self.choose.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl Default for DevicePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<DevicePreference> for DevicePolicy {
+    fn from(preference: DevicePreference) -> Self {
+        Self::new().prefer(preference)
+    }
+}
+
+/// Chooses a backend and how it is set up.
+///
+/// Backends are tried in order until one starts, so that the same program can run with a GPU,
+/// over SSH, or in CI. Options that a backend has no use for are ignored.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub backends: Vec<BackendKind>,
+    pub window: WindowOptions,
+    pub show_fps: bool,
+    pub present_mode: PresentMode,
+    /// Samples per pixel for multisample antialiasing; 1 turns it off.
+    pub samples: u32,
+    pub device: DevicePolicy,
+    /// The most frames to draw per second, sleeping between them. `None` draws as fast as the
+    /// present mode allows.
+    pub max_fps: Option<u32>,
+    /// Pressing the key saves the frame as a timestamped PNG in the directory. Only the Vulkan
+    /// and OpenGL backends take screenshots.
+    pub screenshot_key: Option<(Key, PathBuf)>,
+}
+
+impl Config {
+    /// Tries Vulkan, then OpenGL, then the terminal if stdout is one, leaving out any that
+    /// weren't built. The software backend comes last, so there is always one to fall back to.
+    pub fn new() -> Self {
+        let mut backends: Vec<_> = [BackendKind::Vulkan, BackendKind::OpenGl].iter()
+            .copied()
+            .filter(BackendKind::is_built)
+            .collect();
+        if stdout_is_tty() {
+            backends.push(BackendKind::Terminal);
+        }
+        backends.push(BackendKind::Software);
+        Self {
+            backends,
+            window: WindowOptions::default(),
+            show_fps: false,
+            present_mode: PresentMode::Fifo,
+            samples: 1,
+            device: DevicePolicy::new(),
+            max_fps: None,
+            screenshot_key: None,
+        }
+    }
+
+    /// Uses only `kind`, with no fallback.
+    pub fn backend(mut self, kind: BackendKind) -> Self {
+        self.backends = vec![kind];
+        self
+    }
+
+    /// Tries `kind` if every backend before it fails.
+    pub fn fallback(mut self, kind: BackendKind) -> Self {
+        self.backends.push(kind);
+        self
+    }
+
+    pub fn window(mut self, options: WindowOptions) -> Self {
+        self.window = options;
+        self
+    }
+
+    pub fn show_fps(mut self) -> Self {
+        self.show_fps = true;
+        self
+    }
+
+    pub fn present_mode(mut self, mode: PresentMode) -> Self {
+        self.present_mode = mode;
+        self
+    }
+
+    pub fn msaa(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Takes a `DevicePolicy`, or just a `DevicePreference`.
+    pub fn device<P: Into<DevicePolicy>>(mut self, policy: P) -> Self {
+        self.device = policy.into();
+        self
+    }
+
+    /// Caps the frame rate at `fps`, e.g. to save power, or lifts the cap with `None`. Use
+    /// `Immediate` or `Mailbox` presentation and no cap to measure how fast frames can be drawn.
+    pub fn max_fps(mut self, fps: Option<u32>) -> Self {
+        self.max_fps = fps;
+        self
+    }
+
+    /// While running in a window, pressing `key` saves the frame as a PNG in `directory`.
+    pub fn screenshot_key<P: Into<PathBuf>>(mut self, key: Key, directory: P) -> Self {
+        self.screenshot_key = Some((key, directory.into()));
+        self
+    }
+
+    /// Starts the first backend that works, in the order given by `FLUID_BACKEND` if it is set,
+    /// and by `backends` otherwise. Fails with every backend's error if none of them start, or
+    /// straight away if `FLUID_BACKEND` names a backend that wasn't built.
+    pub fn init(&self) -> Result<Backend, Box<dyn Error>> {
+        let backends = match env::var(BACKEND_VAR) {
+            Ok(names) => parse_backends(&names)?,
+            Err(_) => self.backends.clone(),
+        };
+
+        let mut errors = Vec::new();
+        for kind in backends {
+            match self.start(kind) {
+                Ok(backend) => {
+                    let backend = backend.max_fps(self.max_fps);
+                    return Ok(if self.show_fps { backend.show_fps() } else { backend });
+                },
+                Err(err) => errors.push(format!("{}: {}", kind, err)),
+            }
+        }
+        if errors.is_empty() {
+            return Err(SimpleError::new("No backends to try").into());
+        }
+        Err(SimpleError::new(format!("No backend could be started ({})", errors.join("; "))).into())
+    }
+
+    fn start(&self, kind: BackendKind) -> Result<Backend, Box<dyn Error>> {
+        if matches!(kind, BackendKind::Vulkan | BackendKind::OpenGl) {
+            check_display()?;
+        }
+        Ok(match kind {
+            #[cfg(feature = "vulkan")]
+            BackendKind::Vulkan => Backend::Vulkan(VulkanBackend::with_config(self)?),
+            #[cfg(feature = "opengl")]
+            BackendKind::OpenGl => Backend::OpenGl(GlBackend::with_config(self)?),
+            BackendKind::Software => {
+                let (width, height) = self.window.size;
+                Backend::Software(SoftwareBackend::new()?.size(width, height))
+            },
+            #[cfg(feature = "terminal")]
+            BackendKind::Terminal => Backend::Terminal(TerminalBackend::new()?.title(self.window.title.clone())),
+            #[allow(unreachable_patterns)]
+            _ => return Err(not_built(kind).into()),
+        })
+    }
+}
+
+/// Parses the value of `FLUID_BACKEND`, rejecting backends that weren't built so that asking
+/// for one fails clearly rather than falling through to the next.
+fn parse_backends(names: &str) -> Result<Vec<BackendKind>, SimpleError> {
+    names.split(',').map(|name| {
+        let kind: BackendKind = name.parse()?;
+        if kind.is_built() { Ok(kind) } else { Err(not_built(kind)) }
+    }).collect()
+}
+
+#[cfg(feature = "terminal")]
+fn stdout_is_tty() -> bool {
+    use crossterm::tty::IsTty;
+    std::io::stdout().is_tty()
+}
+
+#[cfg(not(feature = "terminal"))]
+fn stdout_is_tty() -> bool {
+    false
+}
+
+fn not_built(kind: BackendKind) -> SimpleError {
+    let feature = if kind == BackendKind::OpenGl { "opengl" } else { kind.name() };
+    SimpleError::new(format!("{} was requested, but built without the {} feature", kind, feature))
+}
+
+/// Fails if there is no display server to open a window on. winit panics rather than returning
+/// an error in that case, which would stop other backends from being tried.
+fn check_display() -> Result<(), Box<dyn Error>> {
+    let unix = cfg!(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))));
+    if unix && env::var_os("DISPLAY").is_none() && env::var_os("WAYLAND_DISPLAY").is_none() {
+        return Err(SimpleError::new("no display (neither DISPLAY nor WAYLAND_DISPLAY is set)").into());
+    }
+    Ok(())
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_backend_lists() {
+        assert_eq!(parse_backends("Software").unwrap(), [BackendKind::Software]);
+        assert!(parse_backends("software,metal").is_err());
+        for kind in [BackendKind::Vulkan, BackendKind::OpenGl, BackendKind::Terminal].iter() {
+            let names = format!("{}, software", kind);
+            assert_eq!(parse_backends(&names).is_ok(), kind.is_built(), "{}", names);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_software() {
+        let backends = Config::new().backends;
+        assert_eq!(backends.last(), Some(&BackendKind::Software));
+        assert!(backends.iter().all(BackendKind::is_built));
+    }
+}
@@ -0,0 +1,341 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::graphics::font::Font;
+use crate::graphics::{BlendMode, Colour, RenderData, RuntimeParams, TexCoord, Vertex, VertexProducer};
+use crate::graphics::shaping::{FontStack, ShapedLine};
+use crate::graphics::texture::Texture;
+use crate::graphics::transform::Transform;
+use crate::graphics::Rect;
+
+pub mod pdf;
+pub mod svg;
+
+/// A drawing described as vector shapes rather than pixels, for saving as SVG or PDF.
+///
+/// Documents come either from a `ui::Frame`, which knows where its rectangles and text are, or
+/// from the raw output of any `VertexProducer`. Coordinates are in pixels with y pointing down,
+/// the same as layout; a PDF page is one point per pixel.
+///
+/// Colours are written as they are given, so translucent parts blend in sRGB in other viewers
+/// rather than in linear light as the backends do.
+#[derive(Clone)]
+pub struct Document {
+    pub width: f32,
+    pub height: f32,
+    /// Drawn in order, so the last shape is on top.
+    pub shapes: Vec<Shape>,
+}
+
+#[derive(Clone)]
+pub enum Shape {
+    Rect { rect: Rect, colour: Colour },
+    /// Triangles filled with a single colour, drawn as one path so that shared edges don't show.
+    Triangles { triangles: Vec<[(f32, f32); 3]>, colour: Colour },
+    Image(Image),
+    Text(Text),
+    Layer(Layer),
+}
+
+/// Part of a texture, multiplied by `tint` and stretched over `rect`.
+#[derive(Clone)]
+pub struct Image {
+    pub rect: Rect,
+    pub texture: Arc<Texture>,
+    /// The texture coordinates at the top-left and bottom-right corners of `rect`.
+    pub uv_min: TexCoord,
+    pub uv_max: TexCoord,
+    pub tint: Colour,
+}
+
+/// A line of text.
+#[derive(Clone)]
+pub struct Text {
+    pub text: String,
+    pub fonts: FontStack,
+    pub size: f32,
+    pub colour: Colour,
+    /// The left end of the line's baseline.
+    pub x: f32,
+    pub y: f32,
+    /// Added after every space, for justified text.
+    pub word_spacing: f32,
+    /// The same line once shaped, for formats that place each glyph themselves.
+    pub line: ShapedLine,
+}
+
+/// Shapes drawn together, as a single layer if `opacity` or `blend` need one.
+#[derive(Clone)]
+pub struct Layer {
+    pub shapes: Vec<Shape>,
+    pub opacity: f32,
+    pub blend: BlendMode,
+    /// Applied to the shapes and to `clip`.
+    pub transform: Transform,
+    pub clip: Option<Rect>,
+}
+
+impl Layer {
+    pub fn new(shapes: Vec<Shape>) -> Self {
+        Self { shapes, opacity: 1., blend: BlendMode::Normal, transform: Transform::identity(), clip: None }
+    }
+
+    /// Whether the shapes have to be drawn separately and then composited.
+    pub fn isolated(&self) -> bool {
+        self.opacity < 1. || self.blend != BlendMode::Normal
+    }
+}
+
+impl Image {
+    /// The part of the texture that is drawn, tinted, as non-premultiplied RGBA8 pixels at the
+    /// texture's own resolution. Mirrored texture coordinates give a mirrored image.
+    pub fn pixels(&self) -> (u32, u32, Vec<u8>) {
+        let tex_w = self.texture.width();
+        let tex_h = self.texture.height();
+        let (u0, v0) = self.uv_min;
+        let (u1, v1) = self.uv_max;
+        let width = (((u1 - u0).abs() * tex_w as f32).round() as u32).max(1);
+        let height = (((v1 - v0).abs() * tex_h as f32).round() as u32).max(1);
+
+        let (r, g, b, a) = self.tint.rgba();
+        let tint = [r, g, b, a];
+        let source = self.texture.pixels();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            let v = v0 + (y as f32 + 0.5) / height as f32 * (v1 - v0);
+            let ty = ((v * tex_h as f32) as u32).min(tex_h.saturating_sub(1));
+            for x in 0..width {
+                let u = u0 + (x as f32 + 0.5) / width as f32 * (u1 - u0);
+                let tx = ((u * tex_w as f32) as u32).min(tex_w.saturating_sub(1));
+                let i = ((ty * tex_w + tx) * 4) as usize;
+                for channel in 0..4 {
+                    pixels.push((source[i + channel] as f32 * tint[channel].clamp(0., 1.)).round() as u8);
+                }
+            }
+        }
+        (width, height, pixels)
+    }
+}
+
+impl Document {
+    pub fn new(width: f32, height: f32, shapes: Vec<Shape>) -> Self {
+        Self { width, height, shapes }
+    }
+
+    /// Describes the frame `producer` draws in a `width` by `height` window. Only its triangles
+    /// are available, so text comes out as images of its glyphs; prefer `ui::Frame::export`
+    /// where possible.
+    pub fn capture(producer: &mut dyn VertexProducer, width: u16, height: u16) -> Self {
+        let data = producer.get_data(RuntimeParams { window_width: width, window_height: height });
+        Self::new(width as f32, height as f32, shapes(&data))
+    }
+
+    pub fn to_svg(&self) -> String {
+        svg::write(self)
+    }
+
+    pub fn save_svg<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_svg())?;
+        Ok(())
+    }
+
+    pub fn to_pdf(&self) -> Vec<u8> {
+        pdf::write(self)
+    }
+
+    pub fn save_pdf<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_pdf())?;
+        Ok(())
+    }
+}
+
+/// Recovers shapes from geometry. Axis-aligned quads become rectangles, or images if they are
+/// textured, groups become layers, and everything else becomes triangles in the average of
+/// their vertex colours. Textures on anything but a quad are ignored.
+///
+/// A batch with a clip rectangle or blend mode becomes a layer of its own, so it blends as a
+/// whole rather than part by part.
+pub fn shapes(data: &RenderData) -> Vec<Shape> {
+    let point = |index: u32| match data.vertices[index as usize] {
+        Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => (x, y),
+    };
+
+    let mut shapes = Vec::new();
+    for batch in data.batches.iter() {
+        let mut batch_shapes = Vec::new();
+        if let Some(group) = &batch.group {
+            let mut layer = Layer::new(self::shapes(&group.data));
+            layer.opacity = group.opacity;
+            batch_shapes.push(Shape::Layer(layer));
+        } else {
+            let indices = &data.indices[batch.start..batch.start + batch.count];
+            let mut i = 0;
+            while i + 3 <= indices.len() {
+                // Quads are drawn as top-left, bottom-left, top-right, then top-right, bottom-right,
+                // bottom-left.
+                if let [tl, bl, tr, tr2, br, bl2] = *indices.get(i..i + 6).unwrap_or(&[]) {
+                    let (left, top) = point(tl);
+                    let (right, bottom) = point(br);
+                    let axis_aligned = tr == tr2 && bl == bl2
+                        && point(bl) == (left, bottom) && point(tr) == (right, top);
+                    if axis_aligned {
+                        let rect = Rect::new(left.min(right), top.min(bottom), (right - left).abs(),
+                                             (bottom - top).abs());
+                        let colour = data.colours[tl as usize];
+                        let flat = [bl, tr, br].iter().all(|&v| same_colour(data.colours[v as usize], colour));
+                        if let Some(texture) = &batch.texture {
+                            let (mut uv_min, mut uv_max) = (data.tex_coords[tl as usize], data.tex_coords[br as usize]);
+                            // Keep the image the right way round if the quad was flipped.
+                            if right < left {
+                                std::mem::swap(&mut uv_min.0, &mut uv_max.0);
+                            }
+                            if bottom < top {
+                                std::mem::swap(&mut uv_min.1, &mut uv_max.1);
+                            }
+                            push_shape(&mut batch_shapes, Shape::Image(Image {
+                                rect, texture: texture.clone(), uv_min, uv_max, tint: colour,
+                            }));
+                            i += 6;
+                            continue;
+                        }
+                        if flat {
+                            push_shape(&mut batch_shapes, Shape::Rect { rect, colour });
+                            i += 6;
+                            continue;
+                        }
+                    }
+                }
+
+                let triangle = [indices[i], indices[i + 1], indices[i + 2]];
+                let colours: Vec<_> = triangle.iter().map(|&v| data.colours[v as usize].rgba()).collect();
+                let average = |f: fn(&(f32, f32, f32, f32)) -> f32| colours.iter().map(f).sum::<f32>() / 3.;
+                let colour = Colour::Rgba(average(|c| c.0), average(|c| c.1), average(|c| c.2), average(|c| c.3));
+                push_shape(&mut batch_shapes, Shape::Triangles {
+                    triangles: vec![[point(triangle[0]), point(triangle[1]), point(triangle[2])]],
+                    colour,
+                });
+                i += 3;
+            }
+        }
+
+        if batch.clip.is_some() || batch.blend != BlendMode::Normal {
+            let mut layer = Layer::new(batch_shapes);
+            layer.clip = batch.clip;
+            layer.blend = batch.blend;
+            shapes.push(Shape::Layer(layer));
+        } else {
+            shapes.append(&mut batch_shapes);
+        }
+    }
+    shapes
+}
+
+/// Adds `shape`, merging triangles into the previous shape if they have the same colour.
+fn push_shape(shapes: &mut Vec<Shape>, shape: Shape) {
+    if let (Some(Shape::Triangles { triangles, colour }), Shape::Triangles { triangles: more, colour: next })
+            = (shapes.last_mut(), &shape) {
+        if same_colour(*colour, *next) {
+            triangles.extend_from_slice(more);
+            return;
+        }
+    }
+    shapes.push(shape);
+}
+
+fn same_colour(a: Colour, b: Colour) -> bool {
+    a.rgba() == b.rgba()
+}
+
+/// Every font used by text in `shapes`, in the order they first appear.
+fn fonts(shapes: &[Shape]) -> Vec<Arc<Font>> {
+    fn collect(shapes: &[Shape], fonts: &mut Vec<Arc<Font>>) {
+        for shape in shapes.iter() {
+            match shape {
+                Shape::Text(text) => for font in text.fonts.fonts().iter() {
+                    if !fonts.iter().any(|other| Arc::ptr_eq(font, other)) {
+                        fonts.push(font.clone());
+                    }
+                },
+                Shape::Layer(layer) => collect(&layer.shapes, fonts),
+                _ => (),
+            }
+        }
+    }
+
+    let mut fonts = Vec::new();
+    collect(shapes, &mut fonts);
+    fonts
+}
+
+/// Formats a number with at most three decimal places and no trailing zeros.
+/// The six components of an affine transform, in the order both SVG and PDF take them.
+fn matrix(transform: &Transform) -> String {
+    let m = &transform.matrix;
+    [m.x.x, m.x.y, m.y.x, m.y.y, m.z.x, m.z.y].iter().map(|&x| num(x)).collect::<Vec<_>>().join(" ")
+}
+
+fn num(x: f32) -> String {
+    let formatted = format!("{:.3}", x);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" | "" => "0".to_string(),
+        _ => trimmed.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f32 = 20.;
+
+    fn document(font: &Arc<Font>) -> Document {
+        let fonts = FontStack::new(font.clone());
+        let line = fonts.shape_line("x", SIZE, 0., SIZE, 0.);
+        let text = Text {
+            text: "x".to_string(), fonts, size: SIZE, colour: Colour::black(), x: 0., y: SIZE,
+            word_spacing: 0., line,
+        };
+        Document::new(100., 100., vec![Shape::Text(text)])
+    }
+
+    /// The advance of `c` in font units.
+    fn advance_units(font: &Font, c: char) -> f32 {
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(&c.to_string());
+        rustybuzz::shape(&font.face(), &[], buffer).glyph_positions()[0].x_advance as f32
+    }
+
+    #[test]
+    fn sizes_text_by_its_em() {
+        let font = Font::from_bytes(include_bytes!("../../../tests/fonts/DejaVuSansMono.ttf").to_vec()).unwrap();
+        let em_size = font.em_size(SIZE);
+        let units = advance_units(&font, 'x');
+        // An em at the given size is what `Font` advances by.
+        let advance = font.advance(font.glyph_id('x'), SIZE);
+        assert!((units * em_size / font.units_per_em() as f32 - advance).abs() < 1e-3);
+
+        let svg = document(&font).to_svg();
+        assert!(svg.contains(&format!(r#"font-size="{}""#, num(em_size))), "{}", svg);
+
+        let pdf = String::from_utf8_lossy(&document(&font).to_pdf()).into_owned();
+        let width = (units * 1000. / font.units_per_em() as f32).round();
+        assert!(pdf.contains(&format!("/W [{} [{}] ]", font.glyph_id('x'), width)), "{}", pdf);
+    }
+
+    #[test]
+    fn bounds_layers_in_their_own_coordinates() {
+        let rect = Shape::Rect { rect: Rect::new(0., 0., 10., 10.), colour: Colour::red() };
+        let mut layer = Layer::new(vec![rect]);
+        layer.opacity = 0.5;
+        layer.transform = Transform::scale(2., 2.).then(Transform::translate(10., 0.));
+        let document = Document::new(100., 100., vec![Shape::Layer(layer)]);
+
+        assert!(document.to_svg().contains(r#"transform="matrix(2 0 0 2 10 0)""#));
+        // The page, as seen from inside the layer.
+        let pdf = String::from_utf8_lossy(&document.to_pdf()).into_owned();
+        assert!(pdf.contains("/BBox [-5 0 45 50]"), "{}", pdf);
+    }
+}
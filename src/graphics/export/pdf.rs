@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::mem;
+use std::sync::Arc;
+
+use crate::graphics::{BlendMode, Colour, Rect};
+use crate::graphics::export::{fonts, matrix, num, Document, Image, Layer, Shape, Text};
+use crate::graphics::font::Font;
+use crate::graphics::transform::Transform;
+
+/// The character each glyph of a font was drawn for, where it is known.
+type GlyphChars = BTreeMap<u16, Option<char>>;
+
+/// Writes `document` as a single-page PDF.
+///
+/// Fonts are embedded whole and each glyph is placed where the shaper put it, so text looks
+/// exactly as it does on screen and can still be copied. PDF has no additive blending, so
+/// additive layers are drawn with screen instead.
+pub fn write(document: &Document) -> Vec<u8> {
+    let mut pdf = PdfWriter::new();
+    let catalog = pdf.reserve();
+    let pages = pdf.reserve();
+    let page = pdf.reserve();
+    let resources = pdf.reserve();
+
+    let mut content = ContentWriter {
+        pdf,
+        resources,
+        width: document.width,
+        height: document.height,
+        fonts: fonts(&document.shapes).into_iter().map(|font| (font, BTreeMap::new())).collect(),
+        states: Vec::new(),
+        objects: Vec::new(),
+        images: 0,
+        forms: 0,
+        transform: Transform::identity(),
+    };
+
+    // PDF puts the origin at the bottom left with y pointing up, so flip the page over.
+    let mut ops = format!("1 0 0 -1 0 {} cm\n", num(document.height));
+    for shape in document.shapes.iter() {
+        content.shape(&mut ops, shape);
+    }
+    let contents = content.pdf.stream("", ops.as_bytes());
+    let contents = content.pdf.add(contents);
+
+    let mut font_refs = String::new();
+    for (i, (font, glyphs)) in content.fonts.iter().enumerate() {
+        let id = write_font(&mut content.pdf, font, glyphs);
+        let _ = write!(font_refs, "/F{} {} 0 R ", i, id);
+    }
+    let states: String = content.states.iter().enumerate()
+        .map(|(i, (_, dict))| format!("/GS{} {} ", i, dict))
+        .collect();
+    let objects: String = content.objects.iter().map(|(name, id)| format!("/{} {} 0 R ", name, id)).collect();
+
+    let mut pdf = content.pdf;
+    pdf.set(resources, format!("<< /Font << {}>> /ExtGState << {}>> /XObject << {}>> >>",
+                               font_refs, states, objects).into_bytes());
+    pdf.set(page, format!("<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources {} 0 R /Contents {} 0 R \
+                           /Group << /S /Transparency /CS /DeviceRGB >> >>",
+                          pages, num(document.width), num(document.height), resources, contents).into_bytes());
+    pdf.set(pages, format!("<< /Type /Pages /Kids [{} 0 R] /Count 1 >>", page).into_bytes());
+    pdf.set(catalog, format!("<< /Type /Catalog /Pages {} 0 R >>", pages).into_bytes());
+    pdf.finish(catalog)
+}
+
+/// Numbered objects, written out with a cross-reference table at the end.
+struct PdfWriter {
+    objects: Vec<Vec<u8>>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        Self { objects: Vec::new() }
+    }
+
+    /// Allocates an object number to be filled in later with `set`.
+    fn reserve(&mut self) -> usize {
+        self.objects.push(Vec::new());
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, object: Vec<u8>) {
+        self.objects[id - 1] = object;
+    }
+
+    fn add(&mut self, object: Vec<u8>) -> usize {
+        let id = self.reserve();
+        self.set(id, object);
+        id
+    }
+
+    /// A compressed stream, with `entries` added to its dictionary.
+    fn stream(&self, entries: &str, data: &[u8]) -> Vec<u8> {
+        let compressed = deflate::deflate_bytes_zlib(data);
+        let mut object = format!("<< {} /Filter /FlateDecode /Length {} >>\nstream\n", entries, compressed.len())
+            .into_bytes();
+        object.extend_from_slice(&compressed);
+        object.extend_from_slice(b"\nendstream");
+        object
+    }
+
+    fn finish(self, root: usize) -> Vec<u8> {
+        // The comment's high bytes mark the file as binary.
+        let mut out = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(table, "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                       self.objects.len() + 1, root, xref);
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+/// Writes drawing operators, adding the objects and resources they need as it goes. Every
+/// content stream shares one resource dictionary.
+struct ContentWriter {
+    pdf: PdfWriter,
+    resources: usize,
+    width: f32,
+    height: f32,
+    /// Each font used, with the glyphs drawn from it.
+    fonts: Vec<(Arc<Font>, GlyphChars)>,
+    /// Graphics states by alpha (in thousandths) and blend mode, named by position.
+    states: Vec<((u32, BlendMode), String)>,
+    /// Images and forms, by name.
+    objects: Vec<(String, usize)>,
+    images: usize,
+    forms: usize,
+    /// Maps the coordinates shapes are being written in to the page's, through the transforms
+    /// of the layers they are in.
+    transform: Transform,
+}
+
+impl ContentWriter {
+    fn shape(&mut self, ops: &mut String, shape: &Shape) {
+        match shape {
+            Shape::Rect { rect, colour } => {
+                self.begin_fill(ops, *colour);
+                let _ = writeln!(ops, "{} {} {} {} re f Q", num(rect.x), num(rect.y), num(rect.width), num(rect.height));
+            },
+            Shape::Triangles { triangles, colour } => {
+                self.begin_fill(ops, *colour);
+                for [a, b, c] in triangles.iter() {
+                    let _ = writeln!(ops, "{} {} m {} {} l {} {} l h", num(a.0), num(a.1), num(b.0), num(b.1),
+                                     num(c.0), num(c.1));
+                }
+                ops.push_str("f Q\n");
+            },
+            Shape::Image(image) => self.image(ops, image),
+            Shape::Text(text) => self.text(ops, text),
+            Shape::Layer(layer) => self.layer(ops, layer),
+        }
+    }
+
+    /// Saves the graphics state and sets the fill colour, which the caller restores with `Q`.
+    fn begin_fill(&mut self, ops: &mut String, colour: Colour) {
+        let (r, g, b, a) = colour.rgba();
+        ops.push_str("q ");
+        if a < 1. {
+            let state = self.state(a, BlendMode::Normal);
+            let _ = write!(ops, "/{} gs ", state);
+        }
+        let _ = write!(ops, "{} {} {} rg ", num(r), num(g), num(b));
+    }
+
+    /// The name of a graphics state with the given alpha and blend mode.
+    fn state(&mut self, alpha: f32, blend: BlendMode) -> String {
+        let alpha = (alpha.clamp(0., 1.) * 1000.).round() as u32;
+        let index = match self.states.iter().position(|(key, _)| *key == (alpha, blend)) {
+            Some(index) => index,
+            None => {
+                let mode = match blend {
+                    BlendMode::Normal => "Normal",
+                    BlendMode::Multiply => "Multiply",
+                    BlendMode::Screen | BlendMode::Additive => "Screen",
+                    BlendMode::Overlay => "Overlay",
+                };
+                let value = alpha as f32 / 1000.;
+                self.states.push(((alpha, blend),
+                                  format!("<< /Type /ExtGState /ca {} /CA {} /BM /{} >>", value, value, mode)));
+                self.states.len() - 1
+            },
+        };
+        format!("GS{}", index)
+    }
+
+    fn image(&mut self, ops: &mut String, image: &Image) {
+        let (width, height, pixels) = image.pixels();
+        let colours: Vec<u8> = pixels.chunks(4).flat_map(|pixel| pixel[..3].to_vec()).collect();
+        let alpha: Vec<u8> = pixels.chunks(4).map(|pixel| pixel[3]).collect();
+
+        let image_entries = |colour_space: &str| format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8",
+            width, height, colour_space);
+        let mask = self.pdf.stream(&image_entries("DeviceGray"), &alpha);
+        let mask = self.pdf.add(mask);
+        let object = self.pdf.stream(&format!("{} /SMask {} 0 R", image_entries("DeviceRGB"), mask), &colours);
+        let object = self.pdf.add(object);
+
+        let name = format!("Im{}", self.images);
+        self.images += 1;
+        self.objects.push((name.clone(), object));
+
+        // Images fill the unit square with their first row at the top, which is y = 1 here.
+        let rect = &image.rect;
+        let _ = writeln!(ops, "q {} 0 0 {} {} {} cm /{} Do Q", num(rect.width), num(-rect.height), num(rect.x),
+                         num(rect.y + rect.height), name);
+    }
+
+    fn text(&mut self, ops: &mut String, text: &Text) {
+        let fonts: Vec<usize> = text.fonts.fonts().iter()
+            .map(|font| self.fonts.iter().position(|(other, _)| Arc::ptr_eq(font, other)).unwrap_or(0))
+            .collect();
+
+        // Map each glyph back to a character for copying, where the font maps it directly.
+        for c in text.text.chars() {
+            if let Some(font) = text.fonts.fonts().iter().position(|font| font.has_glyph(c)) {
+                let id = text.fonts.fonts()[font].glyph_id(c);
+                self.fonts[fonts[font]].1.insert(id, Some(c));
+            }
+        }
+
+        self.begin_fill(ops, text.colour);
+        ops.push_str("BT\n");
+        let mut current = None;
+        for shaped in text.line.glyphs.iter() {
+            let font = fonts[shaped.font];
+            self.fonts[font].1.entry(shaped.glyph.id).or_insert(None);
+            if current != Some(font) {
+                let em_size = text.fonts.fonts()[shaped.font].em_size(text.size);
+                let _ = writeln!(ops, "/F{} {} Tf", font, num(em_size));
+                current = Some(font);
+            }
+            // Flip each glyph back upright.
+            let _ = writeln!(ops, "1 0 0 -1 {} {} Tm <{:04x}> Tj", num(shaped.glyph.x), num(shaped.glyph.y),
+                             shaped.glyph.id);
+        }
+        ops.push_str("ET Q\n");
+    }
+
+    fn layer(&mut self, ops: &mut String, layer: &Layer) {
+        if layer.opacity <= 0. {
+            return;
+        }
+
+        let cm = if layer.transform.is_identity() {
+            String::new()
+        } else {
+            format!("{} cm\n", matrix(&layer.transform))
+        };
+        let mut inner = String::new();
+        if let Some(clip) = &layer.clip {
+            let _ = writeln!(inner, "{} {} {} {} re W n", num(clip.x), num(clip.y), num(clip.width), num(clip.height));
+        }
+        let parent = self.transform;
+        self.transform = layer.transform.then(parent);
+        for shape in layer.shapes.iter() {
+            self.shape(&mut inner, shape);
+        }
+        let transform = mem::replace(&mut self.transform, parent);
+
+        if !layer.isolated() {
+            let _ = write!(ops, "q\n{}{}Q\n", cm, inner);
+            return;
+        }
+        // Draw the shapes into a transparency group in the layer's own coordinates, then composite
+        // that onto the page. The group's bounds are the part of the page the layer can reach.
+        let page = Rect::new(0., 0., self.width, self.height);
+        let bbox = match transform.invert() {
+            Some(inverse) => inverse.apply_rect(&page),
+            // Everything is squashed flat, so nothing shows.
+            None => return,
+        };
+        let bbox = layer.clip.as_ref().map_or(bbox, |clip| bbox.intersect(clip));
+        let entries = format!("/Type /XObject /Subtype /Form /BBox [{} {} {} {}] /Resources {} 0 R \
+                               /Group << /S /Transparency /I true /CS /DeviceRGB >>",
+                              num(bbox.x), num(bbox.y), num(bbox.x + bbox.width), num(bbox.y + bbox.height),
+                              self.resources);
+        let form = self.pdf.stream(&entries, inner.as_bytes());
+        let form = self.pdf.add(form);
+        let name = format!("Fm{}", self.forms);
+        self.forms += 1;
+        self.objects.push((name.clone(), form));
+
+        let state = self.state(layer.opacity, layer.blend);
+        let _ = write!(ops, "q\n{}/{} gs /{} Do Q\n", cm, state, name);
+    }
+}
+
+/// Embeds `font` as a composite font addressed by glyph id, with the widths of `glyphs` and a
+/// map back to their characters. Returns the font's object number.
+fn write_font(pdf: &mut PdfWriter, font: &Arc<Font>, glyphs: &GlyphChars) -> usize {
+    let data = font.data();
+    // Fonts with CFF outlines are embedded as OpenType; the rest as TrueType.
+    let cff = data.starts_with(b"OTTO");
+    let file = if cff {
+        pdf.stream("/Subtype /OpenType", data)
+    } else {
+        pdf.stream(&format!("/Length1 {}", data.len()), data)
+    };
+    let file = pdf.add(file);
+
+    // PDF wants metrics in thousandths of an em.
+    let em = 1000. / font.units_per_em() as f32;
+    let (ascent, descent, cap_height, bbox) = match rustybuzz::Face::from_slice(data, 0) {
+        Some(face) => {
+            let bbox = face.global_bounding_box();
+            (face.ascender() as f32 * em, face.descender() as f32 * em,
+             face.capital_height().unwrap_or_else(|| face.ascender()) as f32 * em,
+             [bbox.x_min as f32 * em, bbox.y_min as f32 * em, bbox.x_max as f32 * em, bbox.y_max as f32 * em])
+        },
+        None => (800., -200., 700., [0., -200., 1000., 800.]),
+    };
+    // `Font` sizes fonts by their height from ascent to descent instead, so find the size that
+    // makes an em 1000.
+    let size = 1000. / font.em_size(1.);
+
+    let descriptor = format!(
+        "<< /Type /FontDescriptor /FontName /Fluid{} /Flags 4 /FontBBox [{} {} {} {}] /ItalicAngle 0 \
+         /Ascent {} /Descent {} /CapHeight {} /StemV 80 /{} {} 0 R >>",
+        file, bbox[0].round(), bbox[1].round(), bbox[2].round(), bbox[3].round(), ascent.round(), descent.round(),
+        cap_height.round(), if cff { "FontFile3" } else { "FontFile2" }, file);
+    let descriptor = pdf.add(descriptor.into_bytes());
+
+    let widths: String = glyphs.keys()
+        .map(|&id| format!("{} [{}] ", id, font.advance(id, size).round()))
+        .collect();
+    let cid_font = format!(
+        "<< /Type /Font /Subtype /{} /BaseFont /Fluid{} /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) \
+         /Supplement 0 >> /FontDescriptor {} 0 R /W [{}]{} >>",
+        if cff { "CIDFontType0" } else { "CIDFontType2" }, file, descriptor, widths,
+        if cff { "" } else { " /CIDToGIDMap /Identity" });
+    let cid_font = pdf.add(cid_font.into_bytes());
+
+    let to_unicode = pdf.stream("", to_unicode(glyphs).as_bytes());
+    let to_unicode = pdf.add(to_unicode);
+    pdf.add(format!("<< /Type /Font /Subtype /Type0 /BaseFont /Fluid{} /Encoding /Identity-H \
+                     /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>", file, cid_font, to_unicode).into_bytes())
+}
+
+/// A CMap from glyph ids to the characters they were used for.
+fn to_unicode(glyphs: &GlyphChars) -> String {
+    let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+        /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+        /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+        1 begincodespacerange\n<0000> <ffff>\nendcodespacerange\n");
+    let mapped: Vec<(u16, char)> = glyphs.iter().filter_map(|(&id, c)| Some((id, (*c)?))).collect();
+    // A CMap can only hold 100 entries per section.
+    for chunk in mapped.chunks(100) {
+        let _ = writeln!(cmap, "{} beginbfchar", chunk.len());
+        for (id, c) in chunk {
+            let utf16: String = c.encode_utf16(&mut [0; 2]).iter().map(|unit| format!("{:04x}", unit)).collect();
+            let _ = writeln!(cmap, "<{:04x}> <{}>", id, utf16);
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    cmap
+}
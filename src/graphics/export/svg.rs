@@ -0,0 +1,173 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use image::ColorType;
+use image::png::PNGEncoder;
+
+use crate::graphics::{BlendMode, Colour, Rect};
+use crate::graphics::export::{fonts, matrix, num, Document, Image, Layer, Shape, Text};
+use crate::graphics::font::Font;
+
+/// Writes `document` as a standalone SVG file. Fonts and images are embedded as data URLs, and
+/// text is left for the viewer to shape, so it stays selectable.
+pub fn write(document: &Document) -> String {
+    let mut writer = SvgWriter { out: String::new(), fonts: fonts(&document.shapes), next_clip: 0 };
+
+    let (width, height) = (num(document.width), num(document.height));
+    writer.line(&format!(r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+                         width, height, width, height));
+    if !writer.fonts.is_empty() {
+        writer.line("<style>");
+        for (i, font) in writer.fonts.clone().iter().enumerate() {
+            let mime = if font.data().starts_with(b"OTTO") { "font/otf" } else { "font/ttf" };
+            writer.line(&format!(r#"@font-face {{ font-family: "fluid-{}"; src: url("data:{};base64,{}"); }}"#,
+                                 i, mime, base64::encode(font.data())));
+        }
+        writer.line("</style>");
+    }
+    for shape in document.shapes.iter() {
+        writer.shape(shape);
+    }
+    writer.line("</svg>");
+    writer.out
+}
+
+struct SvgWriter {
+    out: String,
+    /// Every font used, numbered by position.
+    fonts: Vec<Arc<Font>>,
+    next_clip: usize,
+}
+
+impl SvgWriter {
+    fn line(&mut self, line: &str) {
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn shape(&mut self, shape: &Shape) {
+        match shape {
+            Shape::Rect { rect, colour } => {
+                let line = format!(r#"<rect x="{}" y="{}" width="{}" height="{}"{}/>"#,
+                                   num(rect.x), num(rect.y), num(rect.width), num(rect.height), fill(*colour));
+                self.line(&line);
+            },
+            Shape::Triangles { triangles, colour } => {
+                let mut path = String::new();
+                for triangle in triangles.iter() {
+                    // Wind every triangle the same way, so that they don't cancel each other out.
+                    let [a, mut b, mut c] = *triangle;
+                    if (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0) < 0. {
+                        std::mem::swap(&mut b, &mut c);
+                    }
+                    let _ = write!(path, "M{} {}L{} {}L{} {}Z", num(a.0), num(a.1), num(b.0), num(b.1),
+                                   num(c.0), num(c.1));
+                }
+                self.line(&format!(r#"<path d="{}"{}/>"#, path, fill(*colour)));
+            },
+            Shape::Image(image) => self.image(image),
+            Shape::Text(text) => self.text(text),
+            Shape::Layer(layer) => self.layer(layer),
+        }
+    }
+
+    fn image(&mut self, image: &Image) {
+        let (width, height, pixels) = image.pixels();
+        let mut png = Vec::new();
+        if PNGEncoder::new(&mut png).encode(&pixels, width, height, ColorType::RGBA(8)).is_err() {
+            return;
+        }
+        let rect = &image.rect;
+        let line = format!(r#"<image x="{}" y="{}" width="{}" height="{}" preserveAspectRatio="none" xlink:href="data:image/png;base64,{}"/>"#,
+                           num(rect.x), num(rect.y), num(rect.width), num(rect.height), base64::encode(&png));
+        self.line(&line);
+    }
+
+    fn text(&mut self, text: &Text) {
+        let families: Vec<String> = text.fonts.fonts().iter()
+            .filter_map(|font| self.fonts.iter().position(|other| Arc::ptr_eq(font, other)))
+            .map(|i| format!("'fluid-{}'", i))
+            .collect();
+        let em_size = text.fonts.primary().em_size(text.size);
+        let mut line = format!(r#"<text x="{}" y="{}" font-family="{}" font-size="{}"{}"#,
+                               num(text.x), num(text.y), families.join(", "), num(em_size), fill(text.colour));
+        if text.word_spacing != 0. {
+            let _ = write!(line, r#" word-spacing="{}""#, num(text.word_spacing));
+        }
+        let _ = write!(line, r#" xml:space="preserve">{}</text>"#, escape(&text.text));
+        self.line(&line);
+    }
+
+    fn layer(&mut self, layer: &Layer) {
+        if layer.opacity <= 0. {
+            return;
+        }
+        let mut group = String::from("<g");
+        if !layer.transform.is_identity() {
+            let _ = write!(group, r#" transform="matrix({})""#, matrix(&layer.transform));
+        }
+        if layer.opacity < 1. {
+            let _ = write!(group, r#" opacity="{}""#, num(layer.opacity));
+        }
+        if layer.blend != BlendMode::Normal {
+            let _ = write!(group, r#" style="mix-blend-mode: {}""#, blend_mode(layer.blend));
+        }
+        if let Some(clip) = &layer.clip {
+            let id = self.clip_path(clip);
+            let _ = write!(group, r#" clip-path="url(#{})""#, id);
+        }
+        group.push('>');
+
+        self.line(&group);
+        for shape in layer.shapes.iter() {
+            self.shape(shape);
+        }
+        self.line("</g>");
+    }
+
+    /// Defines a clip path for `rect`, returning its id.
+    fn clip_path(&mut self, rect: &Rect) -> String {
+        let id = format!("clip-{}", self.next_clip);
+        self.next_clip += 1;
+        let line = format!(r#"<clipPath id="{}"><rect x="{}" y="{}" width="{}" height="{}"/></clipPath>"#,
+                           id, num(rect.x), num(rect.y), num(rect.width), num(rect.height));
+        self.line(&line);
+        id
+    }
+}
+
+/// The fill attributes for `colour`, with its alpha given separately for viewers that don't
+/// understand eight-digit hex colours.
+fn fill(colour: Colour) -> String {
+    let alpha = colour.alpha();
+    if alpha < 1. {
+        format!(r#" fill="{}" fill-opacity="{}""#, colour.with_alpha(1.), num(alpha))
+    } else {
+        format!(r#" fill="{}""#, colour)
+    }
+}
+
+/// The closest CSS blend mode. Additive is `plus-lighter`, which not every viewer supports.
+fn blend_mode(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Normal => "normal",
+        BlendMode::Multiply => "multiply",
+        BlendMode::Screen => "screen",
+        BlendMode::Additive => "plus-lighter",
+        BlendMode::Overlay => "overlay",
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusttype::{point, GlyphId, Scale};
+use simple_error::SimpleError;
+
+use crate::graphics::{Colour, RenderData};
+use crate::graphics::texture::Texture;
+use crate::graphics::util::{Coord, Quad, RenderStack};
+
+const ATLAS_WIDTH: u32 = 512;
+const MAX_ATLAS_HEIGHT: u32 = 4096;
+/// Empty pixels left around each glyph so that linear filtering doesn't bleed between them.
+const GLYPH_PADDING: u32 = 1;
+/// How many times `render_glyphs` rasterises a line while the atlas keeps starting over.
+const MAX_ATLAS_PASSES: usize = 2;
+
+/// Vertical metrics for a font at a given size, in pixels. `descent` is negative.
+#[derive(Debug, Clone, Copy)]
+pub struct LineMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl LineMetrics {
+    /// The distance between consecutive baselines.
+    pub fn line_height(&self) -> f32 {
+        self.ascent - self.descent + self.line_gap
+    }
+}
+
+/// A glyph placed on a line, where (`x`, `y`) is its origin on the baseline.
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+    pub id: u16,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A TrueType or OpenType font.
+///
+/// Each font keeps its own glyph atlas: glyphs are rasterised the first time they are drawn at a
+/// given size, and the atlas texture is shared by every piece of text using the font.
+///
+/// Glyphs are drawn from their outlines in a single colour. Colour glyphs (COLR, CBDT, sbix or
+/// SVG tables) come out as their plain outline, and glyphs with no outline at all, like the
+/// bitmaps in most emoji fonts, aren't drawn.
+pub struct Font {
+    /// Owns the raw font file, which the shaper reads too.
+    font: rusttype::Font<'static>,
+    atlas: Mutex<GlyphAtlas>,
+}
+
+impl Font {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, Box<dyn Error>> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Arc<Self>, Box<dyn Error>> {
+        let font = rusttype::Font::try_from_vec(bytes)
+            .ok_or_else(|| SimpleError::new("Failed to parse font"))?;
+        let font = Self { font, atlas: Mutex::new(GlyphAtlas::new()) };
+        // Check now that the shaper can read it too, so that `face` can't fail later.
+        rustybuzz::Face::from_slice(font.data(), 0)
+            .ok_or_else(|| SimpleError::new("Failed to parse font for shaping"))?;
+        Ok(Arc::new(font))
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match &self.font {
+            rusttype::Font::Owned(face) => face.as_slice(),
+            rusttype::Font::Ref(_) => unreachable!("fonts are always loaded from owned data"),
+        }
+    }
+
+    /// The face the shaper uses. This only finds the font's tables, so it's cheap enough to do
+    /// for each piece of text.
+    pub(crate) fn face(&self) -> rustybuzz::Face<'_> {
+        rustybuzz::Face::from_slice(self.data(), 0).expect("fonts are checked when loaded")
+    }
+
+    pub fn units_per_em(&self) -> u16 {
+        self.font.units_per_em()
+    }
+
+    /// The em size of the font at `size`. Sizes here are the height from ascent to descent, as
+    /// rusttype measures them, while most formats size fonts by their em.
+    pub fn em_size(&self, size: f32) -> f32 {
+        let metrics = self.font.v_metrics_unscaled();
+        size * self.units_per_em() as f32 / (metrics.ascent - metrics.descent)
+    }
+
+    pub fn metrics(&self, size: f32) -> LineMetrics {
+        let metrics = self.font.v_metrics(Scale::uniform(size));
+        LineMetrics {
+            ascent: metrics.ascent,
+            descent: metrics.descent,
+            line_gap: metrics.line_gap,
+        }
+    }
+
+    /// Returns the glyph for `c`, or 0 (the "missing glyph") if the font doesn't have one.
+    pub fn glyph_id(&self, c: char) -> u16 {
+        self.font.glyph(c).id().0
+    }
+
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.glyph_id(c) != 0
+    }
+
+    pub fn advance(&self, glyph: u16, size: f32) -> f32 {
+        self.font.glyph(GlyphId(glyph)).scaled(Scale::uniform(size)).h_metrics().advance_width
+    }
+
+    pub fn kerning(&self, first: u16, second: u16, size: f32) -> f32 {
+        self.font.pair_kerning(Scale::uniform(size), GlyphId(first), GlyphId(second))
+    }
+
+    /// Lays `text` out on a single line, with the first glyph's origin at (`x`, `y`).
+    ///
+    /// This maps characters straight to glyphs, so it only suits simple left-to-right scripts; see
+    /// `shaping::FontStack` for everything else.
+    pub fn layout_line(&self, text: &str, size: f32, x: f32, y: f32) -> Vec<PlacedGlyph> {
+        let mut pen = x;
+        let mut previous = None;
+        text.chars().map(|c| {
+            let id = self.glyph_id(c);
+            if let Some(previous) = previous {
+                pen += self.kerning(previous, id, size);
+            }
+            let glyph = PlacedGlyph { id, x: pen, y };
+            pen += self.advance(id, size);
+            previous = Some(id);
+            glyph
+        }).collect()
+    }
+
+    /// The width of `text` when laid out on a single line.
+    pub fn measure(&self, text: &str, size: f32) -> f32 {
+        let mut width = 0.;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = self.glyph_id(c);
+            if let Some(previous) = previous {
+                width += self.kerning(previous, id, size);
+            }
+            width += self.advance(id, size);
+            previous = Some(id);
+        }
+        width
+    }
+
+    /// Produces a textured quad for each glyph, rasterising any that aren't in the atlas yet.
+    pub fn render_glyphs(&self, glyphs: &[PlacedGlyph], size: f32, colour: Colour) -> RenderData {
+        let mut atlas = self.atlas.lock().unwrap_or_else(|err| err.into_inner());
+
+        // If the atlas fills up part-way through, it starts over; rasterise everything again so
+        // that all of these glyphs end up in the same texture. Each entry keeps the texture it was
+        // drawn into, so even a line too long for one atlas still draws correctly.
+        let mut entries = Vec::new();
+        for _ in 0..MAX_ATLAS_PASSES {
+            let generation = atlas.generation;
+            entries = glyphs.iter().map(|glyph| atlas.get(&self.font, glyph.id, size)).collect();
+            if atlas.generation == generation {
+                break;
+            }
+        }
+
+        let mut stack = RenderStack::new();
+        for (glyph, entry) in glyphs.iter().zip(entries) {
+            if let Some(entry) = entry {
+                let tex_w = entry.texture.width() as f32;
+                let tex_h = entry.texture.height() as f32;
+                let quad = Quad {
+                    top_left: Coord {
+                        x: (glyph.x.round() as i32 + entry.offset_x) as i16,
+                        y: (glyph.y.round() as i32 + entry.offset_y) as i16,
+                    },
+                    width: entry.width as u16,
+                    height: entry.height as u16,
+                    colour,
+                };
+                let uv_min = (entry.x as f32 / tex_w, entry.y as f32 / tex_h);
+                let uv_max = ((entry.x + entry.width) as f32 / tex_w, (entry.y + entry.height) as f32 / tex_h);
+                stack.push(quad.render_textured(entry.texture, uv_min, uv_max));
+            }
+        }
+        stack.build()
+    }
+}
+
+/// Where a rasterised glyph lives in the atlas, and where its bitmap sits relative to its origin.
+#[derive(Debug, Clone)]
+struct AtlasEntry {
+    /// The atlas texture the glyph was drawn into, which stays valid after the atlas starts over.
+    texture: Arc<Texture>,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    offset_x: i32,
+    offset_y: i32,
+}
+
+/// Glyph bitmaps packed into rows ("shelves") of an RGBA texture, stored as white with the
+/// coverage in the alpha channel so that the vertex colour tints them.
+///
+/// New glyphs are written into the texture in place, so backends only upload the area they
+/// cover. The texture is only replaced when the atlas grows or starts over.
+struct GlyphAtlas {
+    texture: Arc<Texture>,
+    /// Keyed by glyph id and the bits of the pixel size. Glyphs with no pixels map to `None`.
+    entries: HashMap<(u16, u32), Option<AtlasEntry>>,
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+    /// Bumped every time the atlas is cleared.
+    generation: u32,
+}
+
+impl GlyphAtlas {
+    fn new() -> Self {
+        Self {
+            texture: Self::blank(ATLAS_WIDTH),
+            entries: HashMap::new(),
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+            generation: 0,
+        }
+    }
+
+    fn blank(height: u32) -> Arc<Texture> {
+        Texture::from_rgba8(ATLAS_WIDTH, height, vec![0; (ATLAS_WIDTH * height * 4) as usize])
+    }
+
+    fn get(&mut self, font: &rusttype::Font<'static>, id: u16, size: f32) -> Option<AtlasEntry> {
+        let key = (id, size.to_bits());
+        if let Some(entry) = self.entries.get(&key) {
+            return entry.clone();
+        }
+
+        let glyph = font.glyph(GlyphId(id)).scaled(Scale::uniform(size)).positioned(point(0., 0.));
+        let entry = glyph.pixel_bounding_box().and_then(|bounds| {
+            let width = bounds.width() as u32;
+            let height = bounds.height() as u32;
+            let (x, y) = self.allocate(width, height)?;
+            let mut pixels = vec![255; (width * height * 4) as usize];
+            glyph.draw(|gx, gy, coverage| {
+                pixels[((gy * width + gx) * 4 + 3) as usize] = (coverage * 255.).round() as u8;
+            });
+            self.texture.update(x, y, width, height, &pixels);
+            Some(AtlasEntry {
+                texture: self.texture.clone(),
+                x,
+                y,
+                width,
+                height,
+                offset_x: bounds.min.x,
+                offset_y: bounds.min.y,
+            })
+        });
+
+        self.entries.insert(key, entry.clone());
+        entry
+    }
+
+    /// Finds space for a `width` by `height` bitmap, growing the atlas or starting it over if
+    /// there's no room. Returns `None` only if the bitmap can never fit.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let padded_w = width + GLYPH_PADDING;
+        let padded_h = height + GLYPH_PADDING;
+        if padded_w > ATLAS_WIDTH || padded_h > MAX_ATLAS_HEIGHT {
+            return None;
+        }
+
+        if self.cursor_x + padded_w > ATLAS_WIDTH {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height;
+            self.row_height = 0;
+        }
+        while self.cursor_y + padded_h > self.texture.height() {
+            if self.texture.height() * 2 <= MAX_ATLAS_HEIGHT {
+                self.grow();
+            } else {
+                self.clear();
+            }
+        }
+
+        let position = (self.cursor_x, self.cursor_y);
+        self.cursor_x += padded_w;
+        self.row_height = self.row_height.max(padded_h);
+        Some(position)
+    }
+
+    /// Doubles the height of the texture. Rows are stored top to bottom, so growing downwards
+    /// keeps every glyph in place.
+    fn grow(&mut self) {
+        let height = self.texture.height() * 2;
+        let mut pixels = self.texture.pixels().clone();
+        pixels.resize((ATLAS_WIDTH * height * 4) as usize, 0);
+        self.texture = Texture::from_rgba8(ATLAS_WIDTH, height, pixels);
+        for entry in self.entries.values_mut().flatten() {
+            entry.texture = self.texture.clone();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.texture = Self::blank(ATLAS_WIDTH);
+        self.entries.clear();
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.row_height = 0;
+        self.generation += 1;
+    }
+}
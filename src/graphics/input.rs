@@ -0,0 +1,164 @@
+/// Input from the user, which every backend reports in the same way. Positions are in window
+/// coordinates, the same ones that layout uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    /// A key was pressed. The text it typed, if any, is reported separately as `Char`.
+    Key { key: Key, modifiers: Modifiers },
+    /// A character was typed.
+    Char(char),
+    MouseMove { x: f32, y: f32 },
+    MouseDown { x: f32, y: f32, button: MouseButton },
+    MouseUp { x: f32, y: f32, button: MouseButton },
+    /// The wheel was scrolled with the pointer at (`x`, `y`), by `lines`. Positive is down.
+    Scroll { x: f32, y: f32, lines: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A key that types a character, given unshifted and in lower case.
+    Character(char),
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Delete,
+    Insert,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// A function key, where `F(1)` is F1.
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Defines `WinitInput`, which converts window events from the winit crate `$winit` into
+/// `InputEvent`s. It's a macro because the OpenGL backend gets an older winit through glutin.
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+macro_rules! winit_input {
+    ($winit:ident) => {
+        /// Remembers where the pointer is, since winit only reports it when it moves.
+        #[derive(Default)]
+        struct WinitInput {
+            x: f32,
+            y: f32,
+        }
+
+        impl WinitInput {
+            fn convert(&mut self, event: &$winit::WindowEvent) -> Option<$crate::graphics::input::InputEvent> {
+                use $crate::graphics::input::{InputEvent, MouseButton};
+                use $winit::{ElementState, MouseScrollDelta, WindowEvent};
+
+                let button = |button: &$winit::MouseButton| match button {
+                    $winit::MouseButton::Left => Some(MouseButton::Left),
+                    $winit::MouseButton::Right => Some(MouseButton::Right),
+                    $winit::MouseButton::Middle => Some(MouseButton::Middle),
+                    $winit::MouseButton::Other(_) => None,
+                };
+                match event {
+                    WindowEvent::CursorMoved { position, .. } => {
+                        self.x = position.x as f32;
+                        self.y = position.y as f32;
+                        Some(InputEvent::MouseMove { x: self.x, y: self.y })
+                    },
+                    WindowEvent::MouseInput { state: ElementState::Pressed, button: pressed, .. } => {
+                        Some(InputEvent::MouseDown { x: self.x, y: self.y, button: button(pressed)? })
+                    },
+                    WindowEvent::MouseInput { state: ElementState::Released, button: released, .. } => {
+                        Some(InputEvent::MouseUp { x: self.x, y: self.y, button: button(released)? })
+                    },
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        // Pixel deltas come from touchpads; count 20 pixels as a line.
+                        let lines = match delta {
+                            MouseScrollDelta::LineDelta(_, y) => -*y,
+                            MouseScrollDelta::PixelDelta(position) => -position.y as f32 / 20.,
+                        };
+                        Some(InputEvent::Scroll { x: self.x, y: self.y, lines })
+                    },
+                    WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Pressed => {
+                        let modifiers = $crate::graphics::input::Modifiers {
+                            shift: input.modifiers.shift,
+                            ctrl: input.modifiers.ctrl,
+                            alt: input.modifiers.alt,
+                        };
+                        Some(InputEvent::Key { key: convert_key(input.virtual_keycode?)?, modifiers })
+                    },
+                    WindowEvent::ReceivedCharacter(c) if !c.is_control() => Some(InputEvent::Char(*c)),
+                    _ => None,
+                }
+            }
+        }
+
+        fn convert_key(key: $winit::VirtualKeyCode) -> Option<$crate::graphics::input::Key> {
+            use $crate::graphics::input::Key;
+            use $winit::VirtualKeyCode as K;
+
+            const LETTERS: [K; 26] = [K::A, K::B, K::C, K::D, K::E, K::F, K::G, K::H, K::I, K::J, K::K, K::L, K::M,
+                                      K::N, K::O, K::P, K::Q, K::R, K::S, K::T, K::U, K::V, K::W, K::X, K::Y, K::Z];
+            const DIGITS: [K; 10] = [K::Key0, K::Key1, K::Key2, K::Key3, K::Key4, K::Key5, K::Key6, K::Key7, K::Key8,
+                                     K::Key9];
+            const NUMPAD: [K; 10] = [K::Numpad0, K::Numpad1, K::Numpad2, K::Numpad3, K::Numpad4, K::Numpad5,
+                                     K::Numpad6, K::Numpad7, K::Numpad8, K::Numpad9];
+            const FUNCTION: [K; 12] = [K::F1, K::F2, K::F3, K::F4, K::F5, K::F6, K::F7, K::F8, K::F9, K::F10, K::F11,
+                                       K::F12];
+            if let Some(i) = LETTERS.iter().position(|&k| k == key) {
+                return Some(Key::Character((b'a' + i as u8) as char));
+            }
+            if let Some(i) = DIGITS.iter().chain(NUMPAD.iter()).position(|&k| k == key) {
+                return Some(Key::Character((b'0' + (i % 10) as u8) as char));
+            }
+            if let Some(i) = FUNCTION.iter().position(|&k| k == key) {
+                return Some(Key::F(i as u8 + 1));
+            }
+            Some(match key {
+                K::Space => Key::Character(' '),
+                K::Minus => Key::Character('-'),
+                K::Equals => Key::Character('='),
+                K::LBracket => Key::Character('['),
+                K::RBracket => Key::Character(']'),
+                K::Backslash => Key::Character('\\'),
+                K::Semicolon => Key::Character(';'),
+                K::Apostrophe => Key::Character('\''),
+                K::Grave => Key::Character('`'),
+                K::Comma => Key::Character(','),
+                K::Period => Key::Character('.'),
+                K::Slash => Key::Character('/'),
+                K::Return => Key::Enter,
+                K::Escape => Key::Escape,
+                K::Back => Key::Backspace,
+                K::Tab => Key::Tab,
+                K::Delete => Key::Delete,
+                K::Insert => Key::Insert,
+                K::Left => Key::Left,
+                K::Right => Key::Right,
+                K::Up => Key::Up,
+                K::Down => Key::Down,
+                K::Home => Key::Home,
+                K::End => Key::End,
+                K::PageUp => Key::PageUp,
+                K::PageDown => Key::PageDown,
+                _ => return None,
+            })
+        }
+    };
+}
+
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+pub(crate) use winit_input;
@@ -1,8 +1,17 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::marker::Sized;
-use crate::graphics::backends::vk::VulkanBackend;
+use std::sync::Arc;
+use crate::graphics::backends::Backend;
+use crate::graphics::config::Config;
+use crate::graphics::input::InputEvent;
+use crate::graphics::texture::Texture;
+use crate::graphics::transform::Transform;
+use crate::graphics::window::WindowOptions;
 
-pub type Index = u16;
+/// Indices are 32-bit, so one frame can hold up to 2^32 vertices.
+pub type Index = u32;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Vertex {
@@ -16,12 +25,15 @@ pub enum Colour {
     Rgba(f32, f32, f32, f32)
 }
 
+/// A texture coordinate, where (0, 0) is the top-left of the texture and (1, 1) the bottom-right.
+pub type TexCoord = (f32, f32);
+
 impl Vertex {
     pub fn from_xy(vertices: &[(f32, f32)]) -> Vec<Self> {
-        vertices.into_iter().map(|(x, y)| Self::Xy(*x, *y)).collect()
+        vertices.iter().map(|(x, y)| Self::Xy(*x, *y)).collect()
     }
     pub fn from_xyz(vertices: &[(f32, f32, f32)]) -> Vec<Self> {
-        vertices.into_iter().map(|(x, y, z)| Self::Xyz(*x, *y, *z)).collect()
+        vertices.iter().map(|(x, y, z)| Self::Xyz(*x, *y, *z)).collect()
     }
 }
 
@@ -33,10 +45,10 @@ impl Colour {
         Self::Rgba(r as f32 / 255., g as f32 / 255., b as f32 / 255., a as f32 / 255.)
     }
     pub fn from_rgb(vertices: &[(f32, f32, f32)]) -> Vec<Self> {
-        vertices.into_iter().map(|(r, g, b)| Self::Rgba(*r, *g, *b, 1.0)).collect()
+        vertices.iter().map(|(r, g, b)| Self::Rgba(*r, *g, *b, 1.0)).collect()
     }
     pub fn from_rgba(vertices: &[(f32, f32, f32, f32)]) -> Vec<Self> {
-        vertices.into_iter().map(|(r, g, b, a)| Self::Rgba(*r, *g, *b, *a)).collect()
+        vertices.iter().map(|(r, g, b, a)| Self::Rgba(*r, *g, *b, *a)).collect()
     }
     pub fn red() -> Self {
         Colour::Rgba(1.0, 0.0, 0.0, 1.0)
@@ -61,21 +73,490 @@ pub struct RuntimeParams {
     pub window_height: u16,
 }
 
-pub type RenderData = (Vec<Vertex>, Vec<Colour>, Vec<Index>);
+/// An axis-aligned rectangle in window coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// The overlap between two rectangles, which may be empty (zero-sized).
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, (right - x).max(0.), (bottom - y).max(0.))
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// How drawn colours combine with what is already below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Paints over what is below.
+    Normal,
+    /// Darkens what is below by multiplying it. Over transparent areas the colour is drawn as it
+    /// is, and GPU backends read what is below as they do for `Overlay`.
+    Multiply,
+    /// Lightens what is below; the inverse of multiply.
+    Screen,
+    /// Adds to what is below.
+    Additive,
+    /// Multiplies dark parts of what is below and screens light parts, so mid-grey leaves it
+    /// unchanged. GPU backends read what is below from a copy made before the batch, so
+    /// triangles in the same batch don't blend with each other.
+    Overlay,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Normal
+    }
+}
+
+impl BlendMode {
+    pub fn all() -> [BlendMode; 5] {
+        [BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen, BlendMode::Additive, BlendMode::Overlay]
+    }
+
+    /// Whether GPU backends have to read what is below in the shader, since blend factors alone
+    /// can't give the right result over translucent areas.
+    pub(crate) fn needs_backdrop(&self) -> bool {
+        matches!(self, BlendMode::Multiply | BlendMode::Overlay)
+    }
+}
+
+/// A run of indices that are drawn together with the same texture, clip rectangle and blend mode.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub start: usize,
+    pub count: usize,
+    /// The texture to sample from; `None` draws the vertex colours as-is.
+    pub texture: Option<Arc<Texture>>,
+    /// Nothing is drawn outside this rectangle, if there is one.
+    pub clip: Option<Rect>,
+    /// If set, the batch's geometry covers `group`, and draws it as one layer in place of
+    /// `texture`.
+    pub group: Option<Arc<Group>>,
+    pub blend: BlendMode,
+    /// Everything drawn before a `Multiply` or `Overlay` batch, which GPU backends blend with; see
+    /// `RenderData::with_backdrops`. The same group is drawn by an earlier batch.
+    pub backdrop: Option<Arc<Group>>,
+}
+
+impl Batch {
+    fn same_state(&self, other: &Batch) -> bool {
+        let same_texture = match (&self.texture, &other.texture) {
+            (Some(a), Some(b)) => a.id() == b.id(),
+            (None, None) => true,
+            _ => false
+        };
+        same_texture && self.clip == other.clip && self.blend == other.blend
+            && self.group.is_none() && other.group.is_none()
+            && self.backdrop.is_none() && other.backdrop.is_none()
+    }
+}
+
+/// Geometry that is drawn into an offscreen image of its own and then composited onto whatever
+/// is below it as a single layer, so that its parts don't show through one another when it is
+/// translucent.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub data: RenderData,
+    pub opacity: f32,
+}
+
+/// Geometry to be drawn. `vertices`, `colours` and `tex_coords` are parallel arrays; `batches`
+/// partition `indices` into draws, in the order they should be drawn.
+#[derive(Debug, Clone, Default)]
+pub struct RenderData {
+    pub vertices: Vec<Vertex>,
+    pub colours: Vec<Colour>,
+    pub tex_coords: Vec<TexCoord>,
+    pub indices: Vec<Index>,
+    pub batches: Vec<Batch>,
+}
+
+impl RenderData {
+    /// Creates untextured geometry drawn in a single batch.
+    pub fn new(vertices: Vec<Vertex>, colours: Vec<Colour>, indices: Vec<Index>) -> Self {
+        let tex_coords = vec![(0., 0.); vertices.len()];
+        let batches = vec![Batch {
+            start: 0, count: indices.len(), texture: None, clip: None, group: None, blend: BlendMode::Normal,
+            backdrop: None,
+        }];
+        Self { vertices, colours, tex_coords, indices, batches }
+    }
+
+    /// Creates geometry drawn in a single batch with the given texture.
+    pub fn textured(vertices: Vec<Vertex>, colours: Vec<Colour>, tex_coords: Vec<TexCoord>,
+                    indices: Vec<Index>, texture: Arc<Texture>) -> Self {
+        let batches = vec![Batch {
+            start: 0, count: indices.len(), texture: Some(texture), clip: None, group: None, blend: BlendMode::Normal,
+            backdrop: None,
+        }];
+        Self { vertices, colours, tex_coords, indices, batches }
+    }
+
+    /// Appends `other` so that it is drawn after (i.e. on top of) this data.
+    ///
+    /// Panics if the combined vertices can't all be reached by an `Index`, rather than letting
+    /// indices wrap around.
+    pub fn append(&mut self, mut other: RenderData) {
+        assert_eq!(other.vertices.len(), other.colours.len());
+        assert_eq!(other.vertices.len(), other.tex_coords.len());
+        // If the total fits, no offset index can overflow.
+        Index::try_from(self.vertices.len() + other.vertices.len()).expect("Too many vertices to index");
+        let base = self.vertices.len() as Index;
+        let index_base = self.indices.len();
+        self.vertices.append(&mut other.vertices);
+        self.colours.append(&mut other.colours);
+        self.tex_coords.append(&mut other.tex_coords);
+        self.indices.extend(other.indices.into_iter().map(|x| x + base));
+
+        for mut batch in other.batches.into_iter().filter(|batch| batch.count > 0) {
+            batch.start += index_base;
+            match self.batches.last_mut() {
+                Some(last) if last.same_state(&batch) && last.start + last.count == batch.start => {
+                    last.count += batch.count;
+                },
+                _ => self.batches.push(batch)
+            }
+        }
+    }
+
+    /// Clips everything to `rect`, on top of any clipping that is already applied.
+    pub fn clip(&mut self, rect: Rect) {
+        for batch in self.batches.iter_mut() {
+            batch.clip = Some(match batch.clip {
+                Some(clip) => clip.intersect(&rect),
+                None => rect,
+            });
+        }
+    }
+
+    /// Blends everything that doesn't already have a blend mode of its own with `mode`.
+    pub fn blend(&mut self, mode: BlendMode) {
+        for batch in self.batches.iter_mut().filter(|batch| batch.blend == BlendMode::Normal) {
+            batch.blend = mode;
+        }
+    }
+
+    /// Applies `transform` to every vertex. Clip rectangles are transformed along with them if
+    /// the transform is axis-aligned; otherwise, clipped geometry is cut down to its clip
+    /// rectangle first, since a rotated rectangle can't be a scissor.
+    pub fn transform(&mut self, transform: &Transform) {
+        if !transform.is_axis_aligned() {
+            self.clip_geometry();
+        }
+        for vertex in self.vertices.iter_mut() {
+            *vertex = match *vertex {
+                Vertex::Xy(x, y) => {
+                    let (x, y) = transform.apply(x, y);
+                    Vertex::Xy(x, y)
+                },
+                Vertex::Xyz(x, y, z) => {
+                    let (x, y) = transform.apply(x, y);
+                    Vertex::Xyz(x, y, z)
+                },
+            };
+        }
+        for batch in self.batches.iter_mut() {
+            batch.clip = batch.clip.map(|clip| transform.apply_rect(&clip));
+            if let Some(group) = batch.group.as_mut() {
+                Arc::make_mut(group).data.transform(transform);
+            }
+        }
+    }
+
+    /// Cuts the triangles of every clipped batch down to its clip rectangle, and drops the clip.
+    /// New vertices are interpolated where triangles cross the edges of a clip.
+    fn clip_geometry(&mut self) {
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for batch in self.batches.iter_mut() {
+            let start = indices.len();
+            let triangles = &self.indices[batch.start..batch.start + batch.count];
+            match batch.clip.take() {
+                None => indices.extend_from_slice(triangles),
+                Some(clip) => for triangle in triangles.chunks_exact(3) {
+                    let polygon = clip_polygon(&mut self.vertices, &mut self.colours, &mut self.tex_coords,
+                                               triangle.to_vec(), &clip);
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                },
+            }
+            batch.start = start;
+            batch.count = indices.len() - start;
+        }
+        self.indices = indices;
+        self.batches.retain(|batch| batch.count > 0);
+    }
+
+    /// Wraps everything up as a `Group` with the given opacity, drawn with a single quad over
+    /// its bounds.
+    pub fn group(self, opacity: f32) -> RenderData {
+        let mut points = self.vertices.iter().map(|vertex| match *vertex {
+            Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => (x, y),
+        });
+        let first = match points.next() {
+            Some(first) => first,
+            None => return RenderData::default(),
+        };
+        let (left, top, right, bottom) = points.fold((first.0, first.1, first.0, first.1), |(l, t, r, b), (x, y)| {
+            (l.min(x), t.min(y), r.max(x), b.max(y))
+        });
+
+        let vertices = Vertex::from_xy(&[(left, top), (left, bottom), (right, top), (right, bottom)]);
+        let colours = vec![Colour::white(); 4];
+        let indices = vec![0, 1, 2, 2, 3, 1];
+        let mut data = RenderData::new(vertices, colours, indices);
+        data.batches[0].group = Some(Arc::new(Group { data: self, opacity }));
+        data
+    }
+
+    /// Prepares multiply and overlay blending for a backend that can't read what it has drawn so
+    /// far.
+    ///
+    /// Everything below the last batch that needs a backdrop becomes a group, which is drawn
+    /// first and then set as the batch's `backdrop`; earlier ones are handled the same way inside
+    /// that group, and inside other groups. Such a batch with nothing below it is drawn normally,
+    /// which is what both modes do over transparency.
+    pub fn with_backdrops(mut self) -> RenderData {
+        for batch in self.batches.iter_mut() {
+            if let Some(group) = batch.group.as_mut().filter(|group| group.data.needs_backdrops()) {
+                let group = Arc::make_mut(group);
+                group.data = std::mem::take(&mut group.data).with_backdrops();
+            }
+        }
+        let last = match self.batches.iter().rposition(|batch| batch.blend.needs_backdrop()) {
+            Some(last) => last,
+            None => return self,
+        };
+
+        // The geometry moves into the backdrop as it is, and only the vertices that the batches
+        // drawn over it use are copied out.
+        let mut above = RenderData { batches: self.batches.split_off(last), ..RenderData::default() };
+        let mut copied = HashMap::new();
+        for batch in above.batches.iter_mut() {
+            let start = above.indices.len();
+            for &index in self.indices[batch.start..batch.start + batch.count].iter() {
+                let copy = match copied.get(&index) {
+                    Some(&copy) => copy,
+                    None => {
+                        let copy = above.vertices.len() as Index;
+                        let i = index as usize;
+                        above.vertices.push(self.vertices[i]);
+                        above.colours.push(self.colours[i]);
+                        above.tex_coords.push(self.tex_coords[i]);
+                        copied.insert(index, copy);
+                        copy
+                    },
+                };
+                above.indices.push(copy);
+            }
+            batch.start = start;
+        }
+
+        let mut data = if self.batches.is_empty() { RenderData::default() } else { self.with_backdrops().group(1.) };
+        match data.batches.first() {
+            Some(backdrop) => above.batches[0].backdrop = backdrop.group.clone(),
+            None => above.batches[0].blend = BlendMode::Normal,
+        }
+        data.append(above);
+        data
+    }
+
+    fn needs_backdrops(&self) -> bool {
+        self.batches.iter().any(|batch| {
+            batch.blend.needs_backdrop() || matches!(&batch.group, Some(group) if group.data.needs_backdrops())
+        })
+    }
+}
+
+/// Clips a convex polygon, given as indices of its corners, to `rect` one edge at a time, adding
+/// a vertex wherever an edge of the polygon crosses an edge of `rect`.
+fn clip_polygon(vertices: &mut Vec<Vertex>, colours: &mut Vec<Colour>, tex_coords: &mut Vec<TexCoord>,
+                mut polygon: Vec<Index>, rect: &Rect) -> Vec<Index> {
+    let position = |vertex: &Vertex| match *vertex {
+        Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => (x, y),
+    };
+    // How far inside each edge a point is.
+    let edges: [&dyn Fn((f32, f32)) -> f32; 4] = [
+        &|(x, _)| x - rect.x,
+        &|(x, _)| rect.x + rect.width - x,
+        &|(_, y)| y - rect.y,
+        &|(_, y)| rect.y + rect.height - y,
+    ];
+
+    for inside in edges.iter() {
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, &a) in polygon.iter().enumerate() {
+            let b = polygon[(i + 1) % polygon.len()];
+            let da = inside(position(&vertices[a as usize]));
+            let db = inside(position(&vertices[b as usize]));
+            if da >= 0. {
+                clipped.push(a);
+            }
+            if (da >= 0.) != (db >= 0.) {
+                let t = da / (da - db);
+                let (a, b) = (a as usize, b as usize);
+                let lerp = |p: f32, q: f32| p + (q - p) * t;
+                let vertex = match (vertices[a], vertices[b]) {
+                    (Vertex::Xy(x1, y1), Vertex::Xy(x2, y2)) => Vertex::Xy(lerp(x1, x2), lerp(y1, y2)),
+                    (first, second) => {
+                        let z = |vertex| match vertex { Vertex::Xyz(_, _, z) => z, Vertex::Xy(..) => 0. };
+                        let ((x1, y1), (x2, y2)) = (position(&first), position(&second));
+                        Vertex::Xyz(lerp(x1, x2), lerp(y1, y2), lerp(z(first), z(second)))
+                    },
+                };
+                let colour = colours[a].mix(&colours[b], t);
+                let tex_coord = (lerp(tex_coords[a].0, tex_coords[b].0), lerp(tex_coords[a].1, tex_coords[b].1));
+                clipped.push(Index::try_from(vertices.len()).expect("Too many vertices to index"));
+                vertices.push(vertex);
+                colours.push(colour);
+                tex_coords.push(tex_coord);
+            }
+        }
+        polygon = clipped;
+        if polygon.is_empty() {
+            break;
+        }
+    }
+    polygon
+}
 
 pub trait VertexProducer {
     fn get_data(&mut self, params: RuntimeParams) -> RenderData;
+
+    /// Called with each input event, between frames. Does nothing by default.
+    fn handle_event(&mut self, _event: InputEvent) {}
+
+    /// Called before each frame. Returning options changes the window to match; `None`, the
+    /// default, leaves it as it is.
+    fn window_options(&mut self) -> Option<WindowOptions> {
+        None
+    }
 }
 
 pub trait GfxProvider {
     fn new() -> Result<Self, Box<dyn Error>> where Self: Sized;
     fn show_fps(self) -> Self;
+    /// Sleeps between frames so that no more than `fps` are drawn per second. `None` doesn't.
+    /// Backends that can't are free to ignore it, which is what this does by default.
+    fn max_fps(self, _fps: Option<u32>) -> Self where Self: Sized {
+        self
+    }
     fn run(self, update_values: Box<dyn VertexProducer>) -> Result<(), Box<dyn Error>>;
 }
 
-pub fn init() -> Result<VulkanBackend, Box<dyn Error>> {
-    VulkanBackend::new()
+/// Starts the first backend that works, with the default `Config`.
+pub fn init() -> Result<Backend, Box<dyn Error>> {
+    Config::new().init()
 }
 
 pub mod backends;
-pub mod util;
\ No newline at end of file
+mod colour;
+pub mod config;
+pub mod export;
+pub mod font;
+pub mod input;
+pub mod shaping;
+pub mod svg;
+pub mod texture;
+pub mod transform;
+pub mod util;
+pub mod window;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(size: f32) -> RenderData {
+        let vertices = Vertex::from_xy(&[(0., 0.), (0., size), (size, 0.), (size, size)]);
+        RenderData::new(vertices, vec![Colour::white(); 4], vec![0, 1, 2, 2, 3, 1])
+    }
+
+    fn area(data: &RenderData) -> f32 {
+        let point = |i: Index| match data.vertices[i as usize] {
+            Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => (x, y),
+        };
+        data.indices.chunks_exact(3).map(|t| {
+            let ((x1, y1), (x2, y2), (x3, y3)) = (point(t[0]), point(t[1]), point(t[2]));
+            ((x2 - x1) * (y3 - y1) - (x3 - x1) * (y2 - y1)).abs() / 2.
+        }).sum()
+    }
+
+    #[test]
+    fn axis_aligned_transforms_move_the_clip() {
+        let mut data = square(10.);
+        data.clip(Rect::new(0., 0., 5., 10.));
+        data.transform(&Transform::scale(2., 1.).then(Transform::translate(1., 0.)));
+        assert_eq!(data.batches[0].clip, Some(Rect::new(1., 0., 10., 10.)));
+        assert_eq!(data.indices.len(), 6);
+    }
+
+    #[test]
+    fn rotations_clip_the_geometry() {
+        let transform = Transform::rotate(cgmath::Deg(30.)).then(Transform::translate(20., 0.));
+        let mut data = square(10.);
+        data.clip(Rect::new(2., -5., 5., 10.));
+        data.transform(&transform);
+
+        assert_eq!(data.batches.len(), 1);
+        assert_eq!(data.batches[0].clip, None);
+        assert!((area(&data) - 25.).abs() < 1e-3);
+        let inverse = transform.invert().unwrap();
+        for &i in data.indices.iter() {
+            let (x, y) = match data.vertices[i as usize] {
+                Vertex::Xy(x, y) | Vertex::Xyz(x, y, _) => inverse.apply(x, y),
+            };
+            assert!(x > 2. - 1e-3 && x < 7. + 1e-3 && y > -1e-3 && y < 5. + 1e-3);
+        }
+    }
+
+    #[test]
+    fn rotations_drop_geometry_outside_the_clip() {
+        let mut data = square(10.);
+        data.clip(Rect::new(20., 0., 5., 5.));
+        data.transform(&Transform::rotate(cgmath::Deg(45.)));
+        assert!(data.indices.is_empty());
+        assert!(data.batches.is_empty());
+    }
+
+    #[test]
+    fn multiply_and_overlay_blend_with_a_backdrop() {
+        for &mode in [BlendMode::Multiply, BlendMode::Overlay].iter() {
+            let mut top = square(5.);
+            top.blend(mode);
+            let mut data = square(10.);
+            data.append(top.clone());
+            let data = data.with_backdrops();
+
+            assert_eq!(data.batches.len(), 2);
+            // The backdrop's quad, and a copy of the square drawn over it.
+            assert_eq!(data.vertices.len(), 8);
+            let below = data.batches[0].group.as_ref().unwrap();
+            assert_eq!(below.data.batches.len(), 1);
+            assert_eq!(data.batches[1].blend, mode);
+            assert!(Arc::ptr_eq(below, data.batches[1].backdrop.as_ref().unwrap()));
+
+            // Over nothing, both draw the source as it is.
+            let alone = top.with_backdrops();
+            assert_eq!(alone.batches.len(), 1);
+            assert_eq!(alone.batches[0].blend, BlendMode::Normal);
+            assert!(alone.batches[0].backdrop.is_none());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::graphics::{Colour, RenderData};
+use crate::graphics::font::{Font, LineMetrics, PlacedGlyph};
+
+/// A shaped glyph, along with the index (into the `FontStack`) of the font it came from.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapedGlyph {
+    pub font: usize,
+    pub glyph: PlacedGlyph,
+    /// The byte offset into the line's text of the first character the glyph came from.
+    pub cluster: usize,
+    /// How far the glyph moves the pen, including any word spacing.
+    pub advance: f32,
+}
+
+/// A line of text that has been shaped and reordered for display.
+#[derive(Debug, Clone)]
+pub struct ShapedLine {
+    /// Glyphs in visual (left-to-right) order.
+    pub glyphs: Vec<ShapedGlyph>,
+    pub width: f32,
+}
+
+impl ShapedLine {
+    /// Moves the whole line `dx` pixels to the right.
+    pub fn offset(self, dx: f32) -> Self {
+        self.translate(dx, 0.)
+    }
+
+    /// Moves the whole line `dx` pixels to the right and `dy` pixels down.
+    pub fn translate(mut self, dx: f32, dy: f32) -> Self {
+        for shaped in self.glyphs.iter_mut() {
+            shaped.glyph.x += dx;
+            shaped.glyph.y += dy;
+        }
+        self
+    }
+}
+
+/// A font together with the fonts to fall back on, in order, for characters it doesn't have.
+///
+/// Fallback only looks at whether a font has a character, not at whether its glyph can be drawn,
+/// so a bitmap-only emoji font won't fill in for missing emoji; see `Font`.
+#[derive(Clone)]
+pub struct FontStack {
+    fonts: Vec<Arc<Font>>,
+}
+
+impl From<Arc<Font>> for FontStack {
+    fn from(font: Arc<Font>) -> Self {
+        Self::new(font)
+    }
+}
+
+impl FontStack {
+    pub fn new(primary: Arc<Font>) -> Self {
+        Self { fonts: vec![primary] }
+    }
+
+    pub fn fallback(mut self, font: Arc<Font>) -> Self {
+        self.fonts.push(font);
+        self
+    }
+
+    pub fn primary(&self) -> &Arc<Font> {
+        &self.fonts[0]
+    }
+
+    /// Every font in the stack, primary first.
+    pub fn fonts(&self) -> &[Arc<Font>] {
+        &self.fonts
+    }
+
+    /// Line metrics come from the primary font, so that fallback doesn't make lines jump about.
+    pub fn metrics(&self, size: f32) -> LineMetrics {
+        self.primary().metrics(size)
+    }
+
+    /// Picks the first font that has every character of a grapheme cluster, ignoring joiners
+    /// and variation selectors; failing that, the first one with its base character.
+    fn font_for(&self, cluster: &str) -> usize {
+        let significant = |c: &char| !matches!(c, '\u{200c}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}');
+        self.fonts.iter()
+            .position(|font| cluster.chars().filter(significant).all(|c| font.has_glyph(c)))
+            .or_else(|| {
+                let base = cluster.chars().next()?;
+                self.fonts.iter().position(|font| font.has_glyph(base))
+            })
+            .unwrap_or(0)
+    }
+
+    /// Shapes `text` as one line whose left edge is at `x`, with the baseline at `y`.
+    ///
+    /// The text is split into bidi runs and put in visual order, then each run is split wherever
+    /// the font changes and shaped. `word_spacing` is added after every space, for justification.
+    pub fn shape_line(&self, text: &str, size: f32, x: f32, y: f32, word_spacing: f32) -> ShapedLine {
+        let mut glyphs = Vec::new();
+        let mut pen = x;
+
+        let bidi = BidiInfo::new(text, None);
+        for paragraph in bidi.paragraphs.iter() {
+            let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+            for run in runs {
+                let rtl = levels[run.start].is_rtl();
+
+                // Split the run into pieces that each use a single font.
+                let mut pieces: Vec<(usize, usize, usize)> = Vec::new();
+                for (offset, cluster) in text[run.clone()].grapheme_indices(true) {
+                    let font = self.font_for(cluster);
+                    let start = run.start + offset;
+                    match pieces.last_mut() {
+                        Some((last_font, _, end)) if *last_font == font => *end = start + cluster.len(),
+                        _ => pieces.push((font, start, start + cluster.len())),
+                    }
+                }
+                if rtl {
+                    pieces.reverse();
+                }
+
+                for (font, start, end) in pieces {
+                    let (piece, advance) = self.shape_piece(font, &text[start..end], rtl, size, word_spacing);
+                    glyphs.extend(piece.into_iter().map(|mut shaped| {
+                        shaped.glyph.x += pen;
+                        shaped.glyph.y += y;
+                        shaped.cluster += start;
+                        shaped
+                    }));
+                    pen += advance;
+                }
+            }
+        }
+
+        ShapedLine { glyphs, width: pen - x }
+    }
+
+    /// Shapes a piece of text that uses a single font and direction, returning glyphs relative
+    /// to an origin at the start of its baseline and to the start of `text`, and its total
+    /// advance.
+    fn shape_piece(&self, font_index: usize, text: &str, rtl: bool, size: f32,
+                   word_spacing: f32) -> (Vec<ShapedGlyph>, f32) {
+        let font = &self.fonts[font_index];
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.set_direction(if rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+        let shaped = rustybuzz::shape(&font.face(), &[], buffer);
+
+        let scale = font.em_size(size) / font.units_per_em() as f32;
+        let mut glyphs = Vec::new();
+        let mut pen = 0.;
+        for (info, position) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+            let mut advance = position.x_advance as f32 * scale;
+            if text[info.cluster as usize..].starts_with(' ') {
+                advance += word_spacing;
+            }
+            glyphs.push(ShapedGlyph {
+                font: font_index,
+                glyph: PlacedGlyph {
+                    id: info.glyph_id as u16,
+                    x: pen + position.x_offset as f32 * scale,
+                    y: -position.y_offset as f32 * scale,
+                },
+                cluster: info.cluster as usize,
+                advance,
+            });
+            pen += advance;
+        }
+        (glyphs, pen)
+    }
+
+    /// The width of `text` once shaped.
+    pub fn measure(&self, text: &str, size: f32) -> f32 {
+        self.shape_line(text, size, 0., 0., 0.).width
+    }
+
+    /// Shapes `text` once and gives the byte offset and advance of each of its grapheme clusters,
+    /// in logical order, so that the width of any part of it is just a sum.
+    pub fn cluster_advances(&self, text: &str, size: f32) -> Vec<(usize, f32)> {
+        let mut advances: Vec<(usize, f32)> = text.grapheme_indices(true).map(|(offset, _)| (offset, 0.)).collect();
+        for glyph in self.shape_line(text, size, 0., 0., 0.).glyphs {
+            let i = advances.partition_point(|(offset, _)| *offset <= glyph.cluster);
+            if let Some((_, advance)) = advances.get_mut(i.saturating_sub(1)) {
+                *advance += glyph.advance;
+            }
+        }
+        advances
+    }
+
+    /// Renders a shaped line, with one batch for each font that it uses.
+    pub fn render(&self, line: &ShapedLine, size: f32, colour: Colour) -> RenderData {
+        let mut data = RenderData::default();
+        for (index, font) in self.fonts.iter().enumerate() {
+            let glyphs: Vec<PlacedGlyph> = line.glyphs.iter()
+                .filter(|glyph| glyph.font == index)
+                .map(|glyph| glyph.glyph)
+                .collect();
+            if !glyphs.is_empty() {
+                data.append(font.render_glyphs(&glyphs, size, colour));
+            }
+        }
+        data
+    }
+}
@@ -0,0 +1,192 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use lyon::math::{point, Point};
+use lyon::path::Path as LyonPath;
+use lyon::tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, LineCap, LineJoin,
+                         StrokeOptions, StrokeTessellator, StrokeVertex, VertexBuffers};
+use lyon::tessellation::FillRule;
+use simple_error::SimpleError;
+use usvg::NodeExt;
+
+use crate::graphics::{Colour, Index, Rect, RenderData, Vertex};
+use crate::graphics::transform::Transform;
+
+/// How far tessellated curves may stray from the real ones, as a fraction of the larger side of
+/// the view box.
+const TOLERANCE: f32 = 0.0005;
+
+/// Vector artwork from an SVG file, tessellated into triangles in the file's own coordinates.
+///
+/// Paths are supported with solid fills and strokes, along with the transforms of their
+/// enclosing groups. Gradients are drawn in the colour of their first stop, and text, images,
+/// filters, masks and dashes are ignored.
+pub struct Svg {
+    data: RenderData,
+    view_box: Rect,
+    width: f32,
+    height: f32,
+}
+
+impl Svg {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, Box<dyn Error>> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Arc<Self>, Box<dyn Error>> {
+        let tree = usvg::Tree::from_data(bytes, &usvg::Options::default())?;
+        let (view_box, width, height) = {
+            let svg = tree.svg_node();
+            let rect = svg.view_box.rect;
+            (Rect::new(rect.x() as f32, rect.y() as f32, rect.width() as f32, rect.height() as f32),
+             svg.size.width() as f32, svg.size.height() as f32)
+        };
+        let tolerance = view_box.width.max(view_box.height) * TOLERANCE;
+
+        let mut data = RenderData::default();
+        for node in tree.root().descendants() {
+            if let usvg::NodeKind::Path(ref path) = *node.borrow() {
+                if path.visibility != usvg::Visibility::Visible {
+                    continue;
+                }
+                // The path's own transform comes first, then those of the groups it's in.
+                let ts = ts_to_transform(&path.transform).then(ts_to_transform(&node.abs_transform()));
+                let opacity = node.ancestors().fold(1., |opacity, ancestor| match *ancestor.borrow() {
+                    usvg::NodeKind::Group(ref group) => opacity * group.opacity.value() as f32,
+                    _ => opacity,
+                });
+                let outline = build_path(&path.data);
+
+                if let Some(fill) = &path.fill {
+                    let colour = paint_colour(&tree, &fill.paint, fill.opacity.value() as f32 * opacity);
+                    let rule = match fill.rule {
+                        usvg::FillRule::NonZero => FillRule::NonZero,
+                        usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+                    };
+                    let mut buffers: VertexBuffers<Point, Index> = VertexBuffers::new();
+                    FillTessellator::new().tessellate_path(
+                        &outline,
+                        &FillOptions::tolerance(tolerance).with_fill_rule(rule),
+                        &mut BuffersBuilder::new(&mut buffers, |vertex: FillVertex| vertex.position()))
+                        .map_err(|err| SimpleError::new(format!("Failed to tessellate path: {:?}", err)))?;
+                    data.append(to_render_data(buffers, &ts, colour));
+                }
+
+                if let Some(stroke) = &path.stroke {
+                    let colour = paint_colour(&tree, &stroke.paint, stroke.opacity.value() as f32 * opacity);
+                    let options = StrokeOptions::tolerance(tolerance)
+                        .with_line_width(stroke.width.value() as f32)
+                        .with_miter_limit(stroke.miterlimit.value() as f32)
+                        .with_line_cap(match stroke.linecap {
+                            usvg::LineCap::Butt => LineCap::Butt,
+                            usvg::LineCap::Round => LineCap::Round,
+                            usvg::LineCap::Square => LineCap::Square,
+                        })
+                        .with_line_join(match stroke.linejoin {
+                            usvg::LineJoin::Miter => LineJoin::Miter,
+                            usvg::LineJoin::Round => LineJoin::Round,
+                            usvg::LineJoin::Bevel => LineJoin::Bevel,
+                        });
+                    let mut buffers: VertexBuffers<Point, Index> = VertexBuffers::new();
+                    StrokeTessellator::new().tessellate_path(
+                        &outline,
+                        &options,
+                        &mut BuffersBuilder::new(&mut buffers, |vertex: StrokeVertex| vertex.position()))
+                        .map_err(|err| SimpleError::new(format!("Failed to tessellate path: {:?}", err)))?;
+                    data.append(to_render_data(buffers, &ts, colour));
+                }
+            }
+        }
+
+        Ok(Arc::new(Self { data, view_box, width, height }))
+    }
+
+    /// The size the file asks to be drawn at, in pixels.
+    pub fn size(&self) -> (f32, f32) {
+        (self.width, self.height)
+    }
+
+    /// Draws the artwork scaled to fit inside `rect`, keeping its aspect ratio and centring it.
+    /// If `colour` is given, it replaces every colour, keeping their alpha.
+    pub fn render(&self, rect: &Rect, colour: Option<Colour>) -> RenderData {
+        let view_box = &self.view_box;
+        if view_box.width <= 0. || view_box.height <= 0. {
+            return RenderData::default();
+        }
+        let scale = (rect.width / view_box.width).min(rect.height / view_box.height);
+        let transform = Transform::translate(-view_box.x, -view_box.y)
+            .then(Transform::scale(scale, scale))
+            .then(Transform::translate(rect.x + (rect.width - view_box.width * scale) / 2.,
+                                       rect.y + (rect.height - view_box.height * scale) / 2.));
+
+        let mut data = self.data.clone();
+        data.transform(&transform);
+        if let Some(colour) = colour {
+            for col in data.colours.iter_mut() {
+                *col = colour.with_alpha(colour.alpha() * col.alpha());
+            }
+        }
+        data
+    }
+}
+
+fn ts_to_transform(ts: &usvg::Transform) -> Transform {
+    let mut transform = Transform::identity();
+    transform.matrix.x.x = ts.a as f32;
+    transform.matrix.x.y = ts.b as f32;
+    transform.matrix.y.x = ts.c as f32;
+    transform.matrix.y.y = ts.d as f32;
+    transform.matrix.z.x = ts.e as f32;
+    transform.matrix.z.y = ts.f as f32;
+    transform
+}
+
+fn build_path(data: &usvg::PathData) -> LyonPath {
+    let mut builder = LyonPath::svg_builder();
+    for segment in data.0.iter() {
+        match *segment {
+            usvg::PathSegment::MoveTo { x, y } => {
+                builder.move_to(point(x as f32, y as f32));
+            },
+            usvg::PathSegment::LineTo { x, y } => {
+                builder.line_to(point(x as f32, y as f32));
+            },
+            usvg::PathSegment::CurveTo { x1, y1, x2, y2, x, y } => {
+                builder.cubic_bezier_to(point(x1 as f32, y1 as f32), point(x2 as f32, y2 as f32),
+                                        point(x as f32, y as f32));
+            },
+            usvg::PathSegment::ClosePath => builder.close(),
+        }
+    }
+    builder.build()
+}
+
+/// The colour to draw `paint` with. Gradients and patterns use their first stop, or black.
+fn paint_colour(tree: &usvg::Tree, paint: &usvg::Paint, opacity: f32) -> Colour {
+    let colour = match paint {
+        usvg::Paint::Color(colour) => Some((*colour, 1.)),
+        usvg::Paint::Link(id) => tree.defs_by_id(id).and_then(|node| match *node.borrow() {
+            usvg::NodeKind::LinearGradient(ref gradient) => gradient.base.stops.first()
+                .map(|stop| (stop.color, stop.opacity.value() as f32)),
+            usvg::NodeKind::RadialGradient(ref gradient) => gradient.base.stops.first()
+                .map(|stop| (stop.color, stop.opacity.value() as f32)),
+            _ => None,
+        }),
+    };
+    let (colour, stop_opacity) = colour.unwrap_or((usvg::Color::black(), 1.));
+    Colour::Rgba(colour.red as f32 / 255., colour.green as f32 / 255., colour.blue as f32 / 255.,
+                 opacity * stop_opacity)
+}
+
+fn to_render_data(buffers: VertexBuffers<Point, Index>, transform: &Transform, colour: Colour) -> RenderData {
+    let vertices: Vec<Vertex> = buffers.vertices.iter()
+        .map(|vertex| {
+            let (x, y) = transform.apply(vertex.x, vertex.y);
+            Vertex::Xy(x, y)
+        })
+        .collect();
+    let colours = vec![colour; vertices.len()];
+    RenderData::new(vertices, colours, buffers.indices)
+}
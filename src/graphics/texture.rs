@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How many updates a texture remembers the area of. Backends that fall further behind than
+/// this upload the whole texture again.
+const MAX_CHANGES: usize = 256;
+
+/// An RGBA8 image held in CPU memory.
+///
+/// Backends upload a texture the first time it appears in a frame, and keep it on the GPU for as
+/// long as it keeps being drawn. Each texture gets a unique id. Its pixels can be changed in
+/// place with `update`, which bumps its `version`; backends then upload the part that changed
+/// before it is next drawn.
+#[derive(Debug)]
+pub struct Texture {
+    id: u64,
+    width: u32,
+    height: u32,
+    pixels: RwLock<Vec<u8>>,
+    version: AtomicU64,
+    /// The version each recent update produced, and the area it changed as (x, y, width, height).
+    changes: Mutex<Vec<(u64, [u32; 4])>>,
+}
+
+impl Texture {
+    /// Creates a texture from tightly-packed, non-premultiplied RGBA8 pixels.
+    pub fn from_rgba8(width: u32, height: u32, pixels: Vec<u8>) -> Arc<Self> {
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        Arc::new(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            width,
+            height,
+            pixels: RwLock::new(pixels),
+            version: AtomicU64::new(0),
+            changes: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Loads a texture from an image file (PNG, JPEG, ...).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, Box<dyn Error>> {
+        Ok(Self::from_image(image::open(path)?))
+    }
+
+    /// Loads a texture from an encoded image held in memory.
+    pub fn from_memory(bytes: &[u8]) -> Result<Arc<Self>, Box<dyn Error>> {
+        Ok(Self::from_image(image::load_from_memory(bytes)?))
+    }
+
+    fn from_image(image: image::DynamicImage) -> Arc<Self> {
+        let image = image.to_rgba();
+        let (width, height) = image.dimensions();
+        Self::from_rgba8(width, height, image.into_raw())
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Tightly-packed, non-premultiplied RGBA8 pixels, row by row.
+    pub fn pixels(&self) -> RwLockReadGuard<'_, Vec<u8>> {
+        self.pixels.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Starts at 0, and goes up with every `update`.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Replaces the `width` by `height` block of pixels with its top-left corner at (`x`, `y`)
+    /// with `pixels`, which are tightly packed like the texture's own.
+    pub fn update(&self, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) {
+        assert!(x + width <= self.width && y + height <= self.height);
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let mut changes = self.changes.lock().unwrap_or_else(|err| err.into_inner());
+        let mut own = self.pixels.write().unwrap_or_else(|err| err.into_inner());
+        let row = (width * 4) as usize;
+        for (i, source) in pixels.chunks_exact(row).enumerate() {
+            let start = (((y + i as u32) * self.width + x) * 4) as usize;
+            own[start..start + row].copy_from_slice(source);
+        }
+        drop(own);
+
+        let version = self.version.fetch_add(1, Ordering::AcqRel) + 1;
+        if changes.len() == MAX_CHANGES {
+            changes.remove(0);
+        }
+        changes.push((version, [x, y, width, height]));
+    }
+
+    /// The smallest area covering every update after `version`, as (x, y, width, height). `None`
+    /// means that the whole texture may have changed.
+    pub fn changed_since(&self, version: u64) -> Option<[u32; 4]> {
+        let changes = self.changes.lock().unwrap_or_else(|err| err.into_inner());
+        let oldest = changes.first().map_or(u64::MAX, |(first, _)| *first);
+        if version + 1 < oldest {
+            return None;
+        }
+        let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+        for (_, [x, y, width, height]) in changes.iter().filter(|(changed, _)| *changed > version) {
+            left = left.min(*x);
+            top = top.min(*y);
+            right = right.max(x + width);
+            bottom = bottom.max(y + height);
+        }
+        Some([left, top, right.saturating_sub(left), bottom.saturating_sub(top)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_writes_in_place_and_tracks_the_changed_area() {
+        let texture = Texture::from_rgba8(4, 4, vec![0; 64]);
+        texture.update(1, 1, 1, 2, &[255; 8]);
+        texture.update(2, 3, 2, 1, &[128; 8]);
+
+        assert_eq!(texture.version(), 2);
+        assert_eq!(&texture.pixels()[(4 + 1) * 4..(4 + 2) * 4], &[255; 4]);
+        assert_eq!(&texture.pixels()[(12 + 3) * 4..(12 + 4) * 4], &[128; 4]);
+        assert_eq!(texture.changed_since(0), Some([1, 1, 3, 3]));
+        assert_eq!(texture.changed_since(1), Some([2, 3, 2, 1]));
+    }
+
+    #[test]
+    fn changed_since_gives_up_on_old_versions() {
+        let texture = Texture::from_rgba8(1, 1, vec![0; 4]);
+        for _ in 0..MAX_CHANGES + 1 {
+            texture.update(0, 0, 1, 1, &[1; 4]);
+        }
+        assert_eq!(texture.changed_since(0), None);
+        assert!(texture.changed_since(1).is_some());
+    }
+}
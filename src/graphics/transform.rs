@@ -0,0 +1,83 @@
+use cgmath::{Matrix3, Point2, Rad, SquareMatrix, Transform as _, Vector3};
+
+use crate::graphics::Rect;
+
+/// A 2D affine transform, stored as a homogeneous 3x3 matrix.
+///
+/// Transforms compose with `then`, so `Transform::rotate(a).then(Transform::translate(x, y))`
+/// rotates first and then translates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: Matrix3<f32>,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self { matrix: Matrix3::identity() }
+    }
+
+    pub fn translate(x: f32, y: f32) -> Self {
+        Self { matrix: Matrix3::new(1., 0., 0., 0., 1., 0., x, y, 1.) }
+    }
+
+    /// Rotates clockwise on screen, since y points down.
+    pub fn rotate<A: Into<Rad<f32>>>(angle: A) -> Self {
+        Self { matrix: Matrix3::from_angle_z(angle) }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Self {
+        Self { matrix: Matrix3::from_diagonal(Vector3::new(x, y, 1.)) }
+    }
+
+    /// Applies `self`, then `next`.
+    pub fn then(self, next: Transform) -> Self {
+        Self { matrix: next.matrix * self.matrix }
+    }
+
+    /// The same transform, applied about `(x, y)` instead of the origin.
+    pub fn about(self, x: f32, y: f32) -> Self {
+        Transform::translate(-x, -y).then(self).then(Transform::translate(x, y))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.matrix == Matrix3::identity()
+    }
+
+    /// Whether rectangles stay axis-aligned, i.e. there's no rotation (other than by right angles)
+    /// or skew.
+    pub fn is_axis_aligned(&self) -> bool {
+        let m = &self.matrix;
+        (m.x.y == 0. && m.y.x == 0.) || (m.x.x == 0. && m.y.y == 0.)
+    }
+
+    pub fn invert(&self) -> Option<Self> {
+        self.matrix.invert().map(|matrix| Self { matrix })
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let p = self.matrix.transform_point(Point2::new(x, y));
+        (p.x, p.y)
+    }
+
+    /// The smallest axis-aligned rectangle containing the transformed `rect`, which is exactly the
+    /// transformed `rect` if the transform is axis-aligned.
+    pub fn apply_rect(&self, rect: &Rect) -> Rect {
+        let corners = [
+            self.apply(rect.x, rect.y),
+            self.apply(rect.x + rect.width, rect.y),
+            self.apply(rect.x, rect.y + rect.height),
+            self.apply(rect.x + rect.width, rect.y + rect.height),
+        ];
+        let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+        let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+        let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max);
+        let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max);
+        Rect::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
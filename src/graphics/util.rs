@@ -1,37 +1,64 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use crate::graphics::*;
 
+/// Sleeps between frames to keep to a frame rate.
+pub(crate) struct FrameLimiter {
+    period: Option<Duration>,
+    next: Instant,
+}
+
+impl FrameLimiter {
+    /// Keeps to at most `fps` frames per second. `None` or 0 never waits.
+    pub(crate) fn new(fps: Option<u32>) -> Self {
+        let period = fps.filter(|&fps| fps > 0).map(|fps| Duration::from_secs(1) / fps);
+        Self { period, next: Instant::now() }
+    }
+
+    /// Sleeps until the next frame is due. A frame that is late starts the schedule again from
+    /// now, rather than the next ones being hurried to catch up.
+    pub(crate) fn wait(&mut self) {
+        if let Some(period) = self.period {
+            let now = Instant::now();
+            if self.next > now {
+                thread::sleep(self.next - now);
+                self.next += period;
+            } else {
+                self.next = now + period;
+            }
+        }
+    }
+}
+
 /// Yes, it's a stack.
 ///
 /// Things get drawn in the reverse order to which they are pushed. Therefore, the most recently-
 /// -pushed thing will appear on the bottom.
+#[derive(Default)]
 pub struct RenderStack {
-    verts: Vec<Vertex>,
-    cols: Vec<Colour>,
-    indices: Vec<Index>,
-    base: usize
+    items: Vec<RenderData>,
 }
 
 impl RenderStack {
     pub fn new() -> Self {
         Self {
-            verts: Vec::new(),
-            cols: Vec::new(),
-            indices: Vec::new(),
-            base: 0
+            items: Vec::new(),
         }
     }
 
-    pub fn push(&mut self, mut data: RenderData) {
-        assert_eq!(data.0.len(), data.1.len());
-        let num_vertices = data.0.len();
-        self.verts.append(&mut data.0);
-        self.cols.append(&mut data.1);
-        self.indices.append(&mut data.2.into_iter().map(|x| x + self.base as u16).collect());
-        self.base += num_vertices;
+    pub fn push(&mut self, data: RenderData) {
+        assert_eq!(data.vertices.len(), data.colours.len());
+        self.items.push(data);
     }
 
     pub fn build(self) -> RenderData {
-        (self.verts, self.cols, self.indices)
+        // Backends draw in order, so the bottom of the stack has to come first.
+        let mut data = RenderData::default();
+        for item in self.items.into_iter().rev() {
+            data.append(item);
+        }
+        data
     }
 }
 
@@ -50,22 +77,41 @@ pub struct Quad {
 }
 
 impl Quad {
-    pub fn render(&self) -> RenderData {
+    fn vertices(&self) -> Vec<Vertex> {
         let left = self.top_left.x as f32;
         let top = self.top_left.y as f32;
         let w = self.width as f32;
         let h = self.height as f32;
-        let vertices = Vertex::from_xy(&[
+        Vertex::from_xy(&[
             (left, top),
             (left, top + h),
             (left + w, top),
             (left + w, top + h)
-        ]);
+        ])
+    }
+
+    fn indices() -> Vec<Index> {
+        vec![
+            0, 1, 2, 2, 3, 1
+        ]
+    }
+
+    pub fn render(&self) -> RenderData {
         let cols = vec![self.colour, self.colour, self.colour, self.colour];
-        let indices = vec![
-            0u16, 1, 2, 2, 3, 1
-        ];
+        RenderData::new(self.vertices(), cols, Self::indices())
+    }
 
-        (vertices, cols, indices)
+    /// Renders the quad with `texture` mapped onto it. `uv_min` and `uv_max` give the region of
+    /// the texture that maps to the top-left and bottom-right corners respectively; the texture
+    /// is multiplied by the quad's colour.
+    pub fn render_textured(&self, texture: Arc<Texture>, uv_min: TexCoord, uv_max: TexCoord) -> RenderData {
+        let cols = vec![self.colour, self.colour, self.colour, self.colour];
+        let tex_coords = vec![
+            (uv_min.0, uv_min.1),
+            (uv_min.0, uv_max.1),
+            (uv_max.0, uv_min.1),
+            (uv_max.0, uv_max.1)
+        ];
+        RenderData::textured(self.vertices(), cols, tex_coords, Self::indices(), texture)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use crate::graphics::texture::Texture;
+
+/// How the window looks and behaves. Sizes and positions are in logical pixels, the same units
+/// as layout.
+///
+/// Backends without a window use what they can: the software backend draws at `size`, and the
+/// terminal backend puts `title` in the terminal's title bar.
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    pub title: String,
+    pub size: (u32, u32),
+    pub min_size: Option<(u32, u32)>,
+    pub max_size: Option<(u32, u32)>,
+    /// Where the top-left corner goes on the desktop. `None` leaves it to the window manager.
+    pub position: Option<(i32, i32)>,
+    pub icon: Option<Arc<Texture>>,
+    pub resizable: bool,
+    /// Covers the monitor the window is on.
+    pub fullscreen: bool,
+    /// Whether the window has a title bar and borders.
+    pub decorations: bool,
+    /// Lets the desktop show through where the frame is transparent, if the platform supports it.
+    pub transparent: bool,
+}
+
+impl WindowOptions {
+    /// A resizable, decorated 800 by 600 window.
+    pub fn new<S: Into<String>>(title: S) -> Self {
+        Self {
+            title: title.into(),
+            size: (800, 600),
+            min_size: None,
+            max_size: None,
+            position: None,
+            icon: None,
+            resizable: true,
+            fullscreen: false,
+            decorations: true,
+            transparent: false,
+        }
+    }
+
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = (width, height);
+        self
+    }
+
+    pub fn min_size(mut self, width: u32, height: u32) -> Self {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    pub fn max_size(mut self, width: u32, height: u32) -> Self {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    pub fn position(mut self, x: i32, y: i32) -> Self {
+        self.position = Some((x, y));
+        self
+    }
+
+    pub fn icon(mut self, icon: Arc<Texture>) -> Self {
+        self.icon = Some(icon);
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// Whether both have the same icon, comparing textures by id.
+    #[cfg(any(feature = "vulkan", feature = "opengl"))]
+    pub(crate) fn same_icon(&self, other: &WindowOptions) -> bool {
+        self.icon.as_ref().map(|icon| icon.id()) == other.icon.as_ref().map(|icon| icon.id())
+    }
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self::new("Fluid")
+    }
+}
+
+/// Defines `window_builder` and `place_window`, which set up a window for the winit crate `$winit`
+/// from `WindowOptions`, and `update_window`, which changes a window to match new options. It's a
+/// macro for the same reason as `winit_input`.
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+macro_rules! winit_window {
+    ($winit:ident) => {
+        fn window_icon(options: &$crate::graphics::window::WindowOptions) -> Option<$winit::Icon> {
+            let icon = options.icon.as_ref()?;
+            $winit::Icon::from_rgba(icon.pixels().to_vec(), icon.width(), icon.height()).ok()
+        }
+
+        fn logical_size((width, height): (u32, u32)) -> $winit::dpi::LogicalSize {
+            $winit::dpi::LogicalSize::new(width as f64, height as f64)
+        }
+
+        /// Everything but the position, which can only be set once the window exists.
+        fn window_builder(options: &$crate::graphics::window::WindowOptions, events_loop: &$winit::EventsLoop)
+                -> $winit::WindowBuilder {
+            let mut builder = $winit::WindowBuilder::new()
+                .with_title(options.title.clone())
+                .with_dimensions(logical_size(options.size))
+                .with_resizable(options.resizable)
+                .with_decorations(options.decorations)
+                .with_transparency(options.transparent)
+                .with_window_icon(window_icon(options));
+            if let Some(size) = options.min_size {
+                builder = builder.with_min_dimensions(logical_size(size));
+            }
+            if let Some(size) = options.max_size {
+                builder = builder.with_max_dimensions(logical_size(size));
+            }
+            if options.fullscreen {
+                builder = builder.with_fullscreen(Some(events_loop.get_primary_monitor()));
+            }
+            builder
+        }
+
+        /// Moves the window to `options.position`, if it has one.
+        fn place_window(window: &$winit::Window, options: &$crate::graphics::window::WindowOptions) {
+            if let Some((x, y)) = options.position {
+                window.set_position($winit::dpi::LogicalPosition::new(x as f64, y as f64));
+            }
+        }
+
+        /// Changes whatever differs between `old` and `new`. Transparency can't be changed once
+        /// the window exists.
+        fn update_window(window: &$winit::Window, old: &$crate::graphics::window::WindowOptions,
+                         new: &$crate::graphics::window::WindowOptions) {
+            if new.title != old.title {
+                window.set_title(&new.title);
+            }
+            if new.size != old.size {
+                window.set_inner_size(logical_size(new.size));
+            }
+            if new.min_size != old.min_size {
+                window.set_min_dimensions(new.min_size.map(logical_size));
+            }
+            if new.max_size != old.max_size {
+                window.set_max_dimensions(new.max_size.map(logical_size));
+            }
+            if new.position != old.position {
+                place_window(window, new);
+            }
+            if !new.same_icon(old) {
+                window.set_window_icon(window_icon(new));
+            }
+            if new.resizable != old.resizable {
+                window.set_resizable(new.resizable);
+            }
+            if new.fullscreen != old.fullscreen {
+                window.set_fullscreen(if new.fullscreen { Some(window.get_current_monitor()) } else { None });
+            }
+            if new.decorations != old.decorations {
+                window.set_decorations(new.decorations);
+            }
+        }
+    };
+}
+
+#[cfg(any(feature = "vulkan", feature = "opengl"))]
+pub(crate) use winit_window;
@@ -1,6 +1,6 @@
-use rust_fluid::graphics;
-
 use rust_fluid::graphics::{GfxProvider, Colour};
+use rust_fluid::graphics::config::Config;
+use rust_fluid::graphics::window::WindowOptions;
 use rust_fluid::ui::{Border, Frame};
 
 fn main() {
@@ -10,7 +10,9 @@ fn main() {
         .border_width(4)
         .border_colour(Colour::white());
 
-    graphics::init().unwrap()
+    Config::new()
+        .window(WindowOptions::new("Fluid demo").min_size(200, 150))
         .show_fps()
+        .init().unwrap()
         .run(Box::new(frame)).unwrap();
 }
\ No newline at end of file
@@ -1,5 +1,15 @@
-use crate::graphics::{Colour, RenderData, RuntimeParams, VertexProducer};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use crate::graphics::{BlendMode, Colour, Index, Rect, RenderData, RuntimeParams, Vertex, VertexProducer};
+use crate::graphics::export::{self, Document, Layer, Shape};
+use crate::graphics::svg::Svg;
+use crate::graphics::texture::Texture;
+use crate::graphics::transform::Transform;
 use crate::graphics::util::{Quad, RenderStack, Coord};
+use crate::ui::text::{Label, Paragraph};
+
+pub mod text;
 
 pub struct Frame {
     frame: Container,
@@ -34,19 +44,99 @@ impl Frame {
         self
     }
 
-    pub fn border_width(mut self, w: u16) -> Self {
-        self.frame.style.border_width = w;
+    /// Takes a `Border`, or a single width for every side.
+    pub fn border_width<B: Into<Border>>(mut self, border: B) -> Self {
+        self.frame.style.border_width = border.into();
+        self
+    }
+
+    pub fn border_colour<C: Into<BorderColour>>(mut self, col: C) -> Self {
+        self.frame.style.border_colour = col.into();
+        self
+    }
+
+    pub fn border_style(mut self, style: BorderStyle) -> Self {
+        self.frame.style.border_style = style;
+        self
+    }
+
+    pub fn background(mut self, background: NineSlice) -> Self {
+        self.frame.style.background = Some(background);
         self
     }
 
-    pub fn border_colour(mut self, col: Colour) -> Self {
-        self.frame.style.border_colour = col;
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.frame.style.overflow = overflow;
         self
     }
+
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.frame.style.opacity = opacity;
+        self
+    }
+
+    pub fn blend(mut self, mode: BlendMode) -> Self {
+        self.frame.style.blend = mode;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.frame.style.transform = transform;
+        self
+    }
+
+    pub fn transform_origin(mut self, x: f32, y: f32) -> Self {
+        self.frame.style.transform_origin = (x, y);
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.frame.flex_direction = direction;
+        self
+    }
+
+    pub fn child<T: Into<GuiObject>>(mut self, obj: T) -> Self {
+        self.frame.push(obj.into());
+        self
+    }
+}
+
+impl Frame {
+    /// Finds the topmost object under the point (`x`, `y`) as of the last layout, as the path of
+    /// child indices leading to it from the root. An empty path means the root itself.
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<Vec<usize>> {
+        let mut path = Vec::new();
+        if self.frame.hit_test(x, y, &mut path) {
+            path.reverse();
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+impl Frame {
+    /// Lays the tree out in a `width` by `height` window and describes it as vector shapes, to
+    /// save as SVG or PDF. Backgrounds and borders come out as rectangles and text as text.
+    pub fn export(&mut self, width: f32, height: f32) -> Document {
+        self.frame.set_top_left(0, 0);
+        self.frame.set_w_h(width, height);
+        self.frame.layout();
+        Document::new(width, height, self.frame.export())
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VertexProducer for Frame {
     fn get_data(&mut self, params: RuntimeParams) -> RenderData {
+        self.frame.set_top_left(0, 0);
+        self.frame.set_w_h(params.window_width as f32, params.window_height as f32);
+        self.frame.layout();
         self.frame.render(&params)
     }
 }
@@ -61,6 +151,72 @@ pub enum GuiObject {
     Component(Component),
 }
 
+impl From<Container> for GuiObject {
+    fn from(container: Container) -> Self {
+        GuiObject::Container(container)
+    }
+}
+
+impl From<Component> for GuiObject {
+    fn from(component: Component) -> Self {
+        GuiObject::Component(component)
+    }
+}
+
+impl GuiObject {
+    fn style(&self) -> &Style {
+        match self {
+            GuiObject::Container(container) => &container.style,
+            GuiObject::Component(component) => &component.style,
+        }
+    }
+
+    /// The size this object would like to be, including its margin, border and padding, if it is
+    /// allowed to be at most `max_width` wide.
+    fn intrinsic_size(&self, max_width: f32) -> (f32, f32) {
+        match self {
+            GuiObject::Container(container) => container.intrinsic_size(max_width),
+            GuiObject::Component(component) => component.intrinsic_size(max_width),
+        }
+    }
+
+    fn set_position(&mut self, left: i16, top: i16, w: f32, h: f32) {
+        match self {
+            GuiObject::Container(container) => {
+                container.set_top_left(left, top);
+                container.set_w_h(w, h);
+                container.layout();
+            },
+            GuiObject::Component(component) => {
+                component.set_top_left(left, top);
+                component.set_w_h(w, h);
+            },
+        }
+    }
+
+    fn render(&self, params: &RuntimeParams) -> RenderData {
+        match self {
+            GuiObject::Container(container) => container.render(params),
+            GuiObject::Component(component) => component.render(params),
+        }
+    }
+
+    fn export(&self) -> Vec<Shape> {
+        match self {
+            GuiObject::Container(container) => container.export(),
+            GuiObject::Component(component) => component.export(),
+        }
+    }
+
+    fn hit_test(&self, x: f32, y: f32, path: &mut Vec<usize>) -> bool {
+        match self {
+            GuiObject::Container(container) => container.hit_test(x, y, path),
+            GuiObject::Component(component) => component.hit_test(x, y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Border {
     pub top: i16,
     pub right: i16,
@@ -75,6 +231,232 @@ impl Border {
     pub fn new(top: i16, right: i16, bottom: i16, left: i16) -> Self {
         Self { top, right, bottom, left }
     }
+    pub fn all(width: i16) -> Self {
+        Self::new(width, width, width, width)
+    }
+
+    /// The same border with negative sides taken as 0.
+    fn non_negative(&self) -> Self {
+        Self::new(self.top.max(0), self.right.max(0), self.bottom.max(0), self.left.max(0))
+    }
+
+    /// `rect` with each side moved inwards by this much.
+    fn shrink(&self, rect: &Rect) -> Rect {
+        Rect::new(rect.x + self.left as f32, rect.y + self.top as f32,
+                  (rect.width - (self.left + self.right) as f32).max(0.),
+                  (rect.height - (self.top + self.bottom) as f32).max(0.))
+    }
+}
+
+/// The colour of each side of a border.
+#[derive(Debug, Clone, Copy)]
+pub struct BorderColour {
+    pub top: Colour,
+    pub right: Colour,
+    pub bottom: Colour,
+    pub left: Colour,
+}
+
+impl BorderColour {
+    pub fn new(top: Colour, right: Colour, bottom: Colour, left: Colour) -> Self {
+        Self { top, right, bottom, left }
+    }
+    pub fn all(col: Colour) -> Self {
+        Self::new(col, col, col, col)
+    }
+}
+
+/// The same width on every side.
+impl From<u16> for Border {
+    fn from(width: u16) -> Self {
+        Self::all(width.min(i16::MAX as u16) as i16)
+    }
+}
+
+impl From<Colour> for BorderColour {
+    fn from(col: Colour) -> Self {
+        Self::all(col)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorderStyle {
+    Solid,
+    /// Dashes three times as long as the border is thick.
+    Dashed,
+    /// Square dots as wide as the border is thick.
+    Dotted,
+}
+
+/// `rect` with its edges rounded to whole pixels.
+fn pixel_rect(rect: &Rect) -> Rect {
+    let left = rect.x.round();
+    let top = rect.y.round();
+    Rect::new(left, top, ((rect.x + rect.width).round() - left).max(0.),
+              ((rect.y + rect.height).round() - top).max(0.))
+}
+
+/// The quad covering `rect`, rounded to whole pixels.
+fn rect_quad(rect: &Rect, colour: Colour) -> Quad {
+    let rect = pixel_rect(rect);
+    Quad {
+        top_left: Coord { x: rect.x as i16, y: rect.y as i16 },
+        width: rect.width as u16,
+        height: rect.height as u16,
+        colour,
+    }
+}
+
+/// The rectangle covering `rect` as it would be drawn, or nothing if it wouldn't show.
+fn rect_shape(rect: &Rect, colour: Colour) -> Option<Shape> {
+    let rect = pixel_rect(rect);
+    if rect.width > 0. && rect.height > 0. && colour.alpha() > 0. {
+        Some(Shape::Rect { rect, colour })
+    } else {
+        None
+    }
+}
+
+impl Style {
+    /// The total horizontal and vertical space taken up by margin, border and padding.
+    fn insets(&self) -> (f32, f32) {
+        let border = self.border_widths();
+        let horizontal = self.margin.left + self.margin.right + border.left + border.right
+            + self.padding.left + self.padding.right;
+        let vertical = self.margin.top + self.margin.bottom + border.top + border.bottom
+            + self.padding.top + self.padding.bottom;
+        (horizontal as f32, vertical as f32)
+    }
+
+    /// The box inside the border.
+    fn padding_box(&self, border_box: &Rect) -> Rect {
+        self.border_widths().shrink(border_box)
+    }
+
+    /// `border_width`, where a negative width draws no border rather than eating into the box.
+    fn border_widths(&self) -> Border {
+        self.border_width.non_negative()
+    }
+
+    /// The box inside the padding, where children or content go.
+    fn content_box(&self, border_box: &Rect) -> Rect {
+        self.padding.shrink(&self.padding_box(border_box))
+    }
+
+    /// Draws each side of the border around `border_box`.
+    fn render_border(&self, border_box: &Rect) -> RenderData {
+        let mut data = RenderData::default();
+        for (rect, colour) in self.border_rects(border_box) {
+            data.append(rect_quad(&rect, colour).render());
+        }
+        data
+    }
+
+    /// The rectangles making up each side of the border around `border_box`, one per dash or
+    /// dot. The top and bottom sides span the whole width, and the left and right sides fit
+    /// between them.
+    fn border_rects(&self, border_box: &Rect) -> Vec<(Rect, Colour)> {
+        let b = border_box;
+        let width = self.border_widths();
+        let (top, right, bottom, left) = (width.top as f32, width.right as f32, width.bottom as f32, width.left as f32);
+        let sides = [
+            (Rect::new(b.x, b.y, b.width, top), self.border_colour.top, true),
+            (Rect::new(b.x, b.y + b.height - bottom, b.width, bottom), self.border_colour.bottom, true),
+            (Rect::new(b.x, b.y + top, left, b.height - top - bottom), self.border_colour.left, false),
+            (Rect::new(b.x + b.width - right, b.y + top, right, b.height - top - bottom), self.border_colour.right, false),
+        ];
+
+        let mut rects = Vec::new();
+        for (rect, colour, horizontal) in sides.iter() {
+            if rect.width > 0. && rect.height > 0. {
+                rects.extend(self.border_side(rect, *horizontal).into_iter().map(|rect| (rect, *colour)));
+            }
+        }
+        rects
+    }
+
+    fn border_side(&self, rect: &Rect, horizontal: bool) -> Vec<Rect> {
+        let (length, thickness) = if horizontal { (rect.width, rect.height) } else { (rect.height, rect.width) };
+        let (dash, gap) = match self.border_style {
+            BorderStyle::Solid => return vec![*rect],
+            BorderStyle::Dashed => (3. * thickness, 2. * thickness),
+            BorderStyle::Dotted => (thickness, thickness),
+        };
+        let dash = dash.min(length);
+
+        // Stretch the gaps so that the side starts and ends with a whole dash.
+        let count = ((length + gap) / (dash + gap)).floor().max(1.);
+        let gap = if count > 1. { (length - count * dash) / (count - 1.) } else { 0. };
+
+        (0..count as usize).map(|i| {
+            let offset = i as f32 * (dash + gap);
+            if horizontal {
+                Rect::new(rect.x + offset, rect.y, dash, rect.height)
+            } else {
+                Rect::new(rect.x, rect.y + offset, rect.width, dash)
+            }
+        }).collect()
+    }
+
+    fn export_border(&self, border_box: &Rect) -> Vec<Shape> {
+        self.border_rects(border_box).iter().filter_map(|(rect, colour)| rect_shape(rect, *colour)).collect()
+    }
+
+    /// Applies `opacity`, `blend` and `transform` to everything an object drew.
+    fn finish(&self, mut data: RenderData, border_box: &Rect) -> RenderData {
+        if self.opacity <= 0. {
+            return RenderData::default();
+        }
+        if self.opacity < 1. {
+            data = data.group(self.opacity);
+        }
+        if self.blend != BlendMode::Normal {
+            data.blend(self.blend);
+        }
+        if !self.transform.is_identity() {
+            data.transform(&self.transform_in(border_box));
+        }
+        data
+    }
+
+    /// The exported equivalent of `finish`.
+    fn finish_export(&self, shapes: Vec<Shape>, border_box: &Rect) -> Vec<Shape> {
+        if self.opacity <= 0. {
+            return Vec::new();
+        }
+        if self.opacity >= 1. && self.blend == BlendMode::Normal && self.transform.is_identity() {
+            return shapes;
+        }
+        let mut layer = Layer::new(shapes);
+        layer.opacity = self.opacity.min(1.);
+        layer.blend = self.blend;
+        if !self.transform.is_identity() {
+            layer.transform = self.transform_in(border_box);
+        }
+        vec![Shape::Layer(layer)]
+    }
+
+    /// The box inside the margin of an object laid out at the given position.
+    fn border_box(&self, left: i16, top: i16, w: f32, h: f32) -> Rect {
+        Rect::new((left + self.margin.left) as f32, (top + self.margin.top) as f32,
+                  (w - (self.margin.left + self.margin.right) as f32).max(0.),
+                  (h - (self.margin.top + self.margin.bottom) as f32).max(0.))
+    }
+
+    /// `transform`, applied about `transform_origin` within `border_box`.
+    fn transform_in(&self, border_box: &Rect) -> Transform {
+        let (x, y) = self.transform_origin;
+        self.transform.about(border_box.x + x * border_box.width, border_box.y + y * border_box.height)
+    }
+
+    /// Maps a point in window coordinates into the untransformed space of an object with this
+    /// style. Returns `None` if the transform can't be undone, e.g. a scale of zero.
+    fn untransform_point(&self, border_box: &Rect, x: f32, y: f32) -> Option<(f32, f32)> {
+        if self.transform.is_identity() {
+            return Some((x, y));
+        }
+        Some(self.transform_in(border_box).invert()?.apply(x, y))
+    }
 }
 
 trait SetPosition {
@@ -83,13 +465,36 @@ trait SetPosition {
     fn set_w_h(&mut self, w: f32, h: f32);
 }
 
+/// Whether a container's children may draw outside its padding box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+}
+
 pub struct Style {
     pub flex: u8,
     pub colour: Colour,
     pub padding: Border,
     pub margin: Border,
-    pub border_width: u16,
-    pub border_colour: Colour,
+    pub border_width: Border,
+    pub border_colour: BorderColour,
+    pub border_style: BorderStyle,
+    /// Drawn over the border box, on top of `colour` and below the border. Only containers draw a
+    /// background.
+    pub background: Option<NineSlice>,
+    pub overflow: Overflow,
+    /// Fades the object and its children as a single layer, from 0 (invisible) to 1 (opaque).
+    pub opacity: f32,
+    /// How the object blends with what is below it. Children with a mode of their own keep it.
+    /// With `opacity` below 1, the object blends as a single layer; otherwise each part of it
+    /// blends separately.
+    pub blend: BlendMode,
+    /// Applied to everything the object and its children draw, after layout.
+    pub transform: Transform,
+    /// The point the transform is applied about, as a fraction of the border box; (0.5, 0.5) is
+    /// the centre.
+    pub transform_origin: (f32, f32),
 }
 
 impl Style {
@@ -99,12 +504,25 @@ impl Style {
             colour: Colour::white(),
             padding: Border::zero(),
             margin: Border::zero(),
-            border_width: 0,
-            border_colour: Colour::black()
+            border_width: Border::zero(),
+            border_colour: BorderColour::all(Colour::black()),
+            border_style: BorderStyle::Solid,
+            background: None,
+            overflow: Overflow::Visible,
+            opacity: 1.,
+            blend: BlendMode::Normal,
+            transform: Transform::identity(),
+            transform_origin: (0.5, 0.5),
         }
     }
 }
 
+impl Default for Style {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Container {
     pub flex_direction: Direction,
     children: Vec<GuiObject>,
@@ -144,38 +562,155 @@ impl Container {
         self.children.push(obj);
     }
 
+    /// The space the children need laid end to end along `flex_direction`, plus this container's
+    /// own insets.
+    fn intrinsic_size(&self, max_width: f32) -> (f32, f32) {
+        let (inset_w, inset_h) = self.style.insets();
+        let inner_max = (max_width - inset_w).max(0.);
+        let (w, h) = self.children.iter().fold((0f32, 0f32), |(w, h), child| {
+            let (child_w, child_h) = child.intrinsic_size(inner_max);
+            match self.flex_direction {
+                Direction::Row => (w + child_w, h.max(child_h)),
+                Direction::Column => (w.max(child_w), h + child_h),
+            }
+        });
+        (w + inset_w, h + inset_h)
+    }
+
+    /// Positions the children inside this container's padding box. Children with a `flex` of 0
+    /// take their intrinsic size along `flex_direction` (so a column grows to fit wrapped text);
+    /// the rest share out the remaining space in proportion to their `flex`.
+    fn layout(&mut self) {
+        let content_box = self.style.content_box(&self.border_box());
+        let left = content_box.x as i16;
+        let top = content_box.y as i16;
+        let inner_w = content_box.width;
+        let inner_h = content_box.height;
+
+        let main_intrinsic = |child: &GuiObject| {
+            let (w, h) = child.intrinsic_size(inner_w);
+            match self.flex_direction {
+                Direction::Row => w,
+                Direction::Column => h,
+            }
+        };
+        let total_flex: u32 = self.children.iter().map(|child| child.style().flex as u32).sum();
+        // Measured once each, since measuring wraps text.
+        let intrinsic: Vec<Option<f32>> = self.children.iter()
+            .map(|child| if child.style().flex == 0 { Some(main_intrinsic(child)) } else { None })
+            .collect();
+        let fixed: f32 = intrinsic.iter().flatten().sum();
+        let main_size = match self.flex_direction {
+            Direction::Row => inner_w,
+            Direction::Column => inner_h,
+        };
+        let flexible = (main_size - fixed).max(0.);
+
+        let sizes: Vec<f32> = self.children.iter().zip(intrinsic).map(|(child, intrinsic)| {
+            intrinsic.unwrap_or_else(|| flexible * child.style().flex as f32 / total_flex as f32)
+        }).collect();
+
+        let mut offset = 0.;
+        for (child, size) in self.children.iter_mut().zip(sizes) {
+            match self.flex_direction {
+                Direction::Row => child.set_position(left + offset as i16, top, size, inner_h),
+                Direction::Column => child.set_position(left, top + offset as i16, inner_w, size),
+            }
+            offset += size;
+        }
+    }
+
+    /// Draws in the box from the last layout, in pixels. Containers are laid out by their parent,
+    /// or by `Frame` for the root.
     pub fn render(&self, params: &RuntimeParams) -> RenderData {
         let mut stack = RenderStack::new();
-        let border_quad = Quad {
-            top_left: Coord {
-                x: self.left + self.style.margin.left,
-                y: self.top + self.style.margin.top
-            },
-            width: ((self.w * params.window_width as f32) as i16
-                - self.style.margin.right
-                - self.style.margin.left) as u16,
-            height: ((self.h * params.window_height as f32) as i16
-                - self.style.margin.bottom
-                - self.style.margin.top) as u16,
-            colour: self.style.border_colour,
-        };
+        let border_box = self.border_box();
+        let padding_box = self.style.padding_box(&border_box);
 
-        let mut content_quad = border_quad;
-        content_quad.colour = self.style.colour;
-        content_quad.top_left.x += self.style.border_width as i16;
-        content_quad.top_left.y += self.style.border_width as i16;
-        content_quad.width -= self.style.border_width * 2;
-        content_quad.height -= self.style.border_width * 2;
+        let mut children = RenderStack::new();
+        for child in self.children.iter() {
+            children.push(child.render(params));
+        }
+        let mut children = children.build();
+        if self.style.overflow == Overflow::Hidden {
+            children.clip(padding_box);
+        }
 
-        stack.push(content_quad.render());
-        stack.push(border_quad.render());
+        stack.push(children);
+        stack.push(self.style.render_border(&border_box));
+        if let Some(background) = &self.style.background {
+            stack.push(background.render(&border_box));
+        }
+        stack.push(rect_quad(&padding_box, self.style.colour).render());
 
-        stack.build()
+        self.style.finish(stack.build(), &border_box)
     }
+
+    /// Shapes are listed bottom first, the reverse of `render`'s stack.
+    fn export(&self) -> Vec<Shape> {
+        let border_box = self.border_box();
+        let padding_box = self.style.padding_box(&border_box);
+
+        let mut shapes: Vec<Shape> = rect_shape(&padding_box, self.style.colour).into_iter().collect();
+        if let Some(background) = &self.style.background {
+            shapes.extend(export::shapes(&background.render(&border_box)));
+        }
+        shapes.extend(self.style.export_border(&border_box));
+        let children: Vec<Shape> = self.children.iter().rev().flat_map(|child| child.export()).collect();
+        if self.style.overflow == Overflow::Hidden {
+            let mut layer = Layer::new(children);
+            layer.clip = Some(padding_box);
+            shapes.push(Shape::Layer(layer));
+        } else {
+            shapes.extend(children);
+        }
+
+        self.style.finish_export(shapes, &border_box)
+    }
+
+    fn border_box(&self) -> Rect {
+        self.style.border_box(self.left, self.top, self.w, self.h)
+    }
+
+    /// Children are drawn with the first on top, so they are tested in order before the
+    /// container itself. Indices are pushed onto `path` innermost first.
+    fn hit_test(&self, x: f32, y: f32, path: &mut Vec<usize>) -> bool {
+        let border_box = self.border_box();
+        let (x, y) = match self.style.untransform_point(&border_box, x, y) {
+            Some(point) => point,
+            None => return false,
+        };
+
+        let padding_box = self.style.padding_box(&border_box);
+        if self.style.overflow == Overflow::Visible || padding_box.contains(x, y) {
+            for (i, child) in self.children.iter().enumerate() {
+                if child.hit_test(x, y, path) {
+                    path.push(i);
+                    return true;
+                }
+            }
+        }
+        border_box.contains(x, y)
+    }
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a `Component` draws inside its layout box.
+pub enum Content {
+    Icon(Icon),
+    Image(Image),
+    Label(Label),
+    Paragraph(Paragraph),
 }
 
 pub struct Component {
     pub style: Style,
+    pub content: Content,
     left: i16,
     top: i16,
     w: f32,
@@ -192,4 +727,286 @@ impl SetPosition for Component {
         self.w = w;
         self.h = h;
     }
-}
\ No newline at end of file
+}
+
+impl Component {
+    pub fn new(content: Content) -> Self {
+        Self {
+            style: Style::new(),
+            content,
+            left: 0,
+            top: 0,
+            w: 1.,
+            h: 1.,
+        }
+    }
+
+    fn intrinsic_size(&self, max_width: f32) -> (f32, f32) {
+        let (inset_w, inset_h) = self.style.insets();
+        let inner_max = (max_width - inset_w).max(0.);
+        let (w, h) = match &self.content {
+            Content::Icon(icon) => icon.intrinsic_size(),
+            Content::Image(image) => image.intrinsic_size(),
+            Content::Label(label) => label.intrinsic_size(),
+            Content::Paragraph(paragraph) => paragraph.intrinsic_size(inner_max),
+        };
+        (w + inset_w, h + inset_h)
+    }
+
+    pub fn render(&self, _params: &RuntimeParams) -> RenderData {
+        let border_box = self.border_box();
+        let content_box = self.style.content_box(&border_box);
+        let (left, top, w, h) = (content_box.x, content_box.y, content_box.width, content_box.height);
+        let mut data = self.style.render_border(&border_box);
+        data.append(match &self.content {
+            Content::Icon(icon) => icon.render(left, top, w, h),
+            Content::Image(image) => image.render(left, top, w, h),
+            Content::Label(label) => label.render(left, top, w, h),
+            Content::Paragraph(paragraph) => paragraph.render(left, top, w, h),
+        });
+        self.style.finish(data, &border_box)
+    }
+
+    fn export(&self) -> Vec<Shape> {
+        let border_box = self.border_box();
+        let content_box = self.style.content_box(&border_box);
+        let (left, top, w, h) = (content_box.x, content_box.y, content_box.width, content_box.height);
+        let mut shapes = self.style.export_border(&border_box);
+        match &self.content {
+            Content::Icon(icon) => shapes.extend(export::shapes(&icon.render(left, top, w, h))),
+            Content::Image(image) => shapes.extend(export::shapes(&image.render(left, top, w, h))),
+            Content::Label(label) => shapes.push(Shape::Text(label.export(left, top, w, h))),
+            Content::Paragraph(paragraph) => {
+                shapes.extend(paragraph.export(left, top, w, h).into_iter().map(Shape::Text));
+            },
+        }
+        self.style.finish_export(shapes, &border_box)
+    }
+
+    fn border_box(&self) -> Rect {
+        self.style.border_box(self.left, self.top, self.w, self.h)
+    }
+
+    fn hit_test(&self, x: f32, y: f32) -> bool {
+        let border_box = self.border_box();
+        match self.style.untransform_point(&border_box, x, y) {
+            Some((x, y)) => border_box.contains(x, y),
+            None => false,
+        }
+    }
+}
+
+/// An image split into a 3x3 grid by four insets, for panels and frames. The corners are drawn at
+/// their own size, the edges stretch along their length, and the centre stretches both ways.
+#[derive(Clone)]
+pub struct NineSlice {
+    texture: Arc<Texture>,
+    /// Where to cut the texture, in texels from each side.
+    pub insets: Border,
+    pub tint: Colour,
+}
+
+impl NineSlice {
+    pub fn new(texture: Arc<Texture>, insets: Border) -> Self {
+        Self { texture, insets, tint: Colour::white() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, insets: Border) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Texture::load(path)?, insets))
+    }
+
+    pub fn tint(mut self, col: Colour) -> Self {
+        self.tint = col;
+        self
+    }
+
+    pub fn texture(&self) -> &Arc<Texture> {
+        &self.texture
+    }
+
+    /// Draws all nine pieces with a single batch, stretched over `rect`. If `rect` is too small
+    /// for the corners, they shrink to fit.
+    pub fn render(&self, rect: &Rect) -> RenderData {
+        let tex_w = self.texture.width() as f32;
+        let tex_h = self.texture.height() as f32;
+        if rect.width <= 0. || rect.height <= 0. || tex_w <= 0. || tex_h <= 0. {
+            return RenderData::default();
+        }
+
+        let insets = &self.insets;
+        let (left, right) = (insets.left.max(0) as f32, insets.right.max(0) as f32);
+        let (top, bottom) = (insets.top.max(0) as f32, insets.bottom.max(0) as f32);
+        let scale_x = (rect.width / (left + right)).min(1.);
+        let scale_y = (rect.height / (top + bottom)).min(1.);
+
+        // Grid lines, on screen and in the texture.
+        let xs = [rect.x, rect.x + left * scale_x, rect.x + rect.width - right * scale_x, rect.x + rect.width];
+        let ys = [rect.y, rect.y + top * scale_y, rect.y + rect.height - bottom * scale_y, rect.y + rect.height];
+        let us = [0., left / tex_w, 1. - right / tex_w, 1.];
+        let vs = [0., top / tex_h, 1. - bottom / tex_h, 1.];
+
+        let mut vertices = Vec::with_capacity(16);
+        let mut tex_coords = Vec::with_capacity(16);
+        for row in 0..4 {
+            for col in 0..4 {
+                vertices.push(Vertex::Xy(xs[col], ys[row]));
+                tex_coords.push((us[col], vs[row]));
+            }
+        }
+        let mut indices = Vec::with_capacity(54);
+        for row in 0..3 {
+            for col in 0..3 {
+                let top_left = (row * 4 + col) as Index;
+                let (top_right, bottom_left, bottom_right) = (top_left + 1, top_left + 4, top_left + 5);
+                indices.extend_from_slice(&[top_left, bottom_left, top_right, top_right, bottom_right, bottom_left]);
+            }
+        }
+        let colours = vec![self.tint; vertices.len()];
+        RenderData::textured(vertices, colours, tex_coords, indices, self.texture.clone())
+    }
+}
+
+impl From<Image> for Component {
+    fn from(image: Image) -> Self {
+        Component::new(Content::Image(image))
+    }
+}
+
+impl From<Image> for GuiObject {
+    fn from(image: Image) -> Self {
+        GuiObject::Component(image.into())
+    }
+}
+
+/// How an image is sized to fit the box it is laid out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFit {
+    /// Scale to fit entirely inside the box, keeping the aspect ratio.
+    Contain,
+    /// Scale to cover the whole box, keeping the aspect ratio and cropping the excess.
+    Cover,
+    /// Stretch to fill the box exactly.
+    Fill,
+    /// Draw at the texture's own size, cropped to the box.
+    None,
+}
+
+pub struct Image {
+    texture: Arc<Texture>,
+    pub fit: ImageFit,
+    pub tint: Colour,
+}
+
+impl Image {
+    pub fn new(texture: Arc<Texture>) -> Self {
+        Self { texture, fit: ImageFit::Contain, tint: Colour::white() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Texture::load(path)?))
+    }
+
+    pub fn fit(mut self, fit: ImageFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    pub fn tint(mut self, col: Colour) -> Self {
+        self.tint = col;
+        self
+    }
+
+    pub fn texture(&self) -> &Arc<Texture> {
+        &self.texture
+    }
+
+    fn intrinsic_size(&self) -> (f32, f32) {
+        (self.texture.width() as f32, self.texture.height() as f32)
+    }
+
+    fn render(&self, left: f32, top: f32, w: f32, h: f32) -> RenderData {
+        let tex_w = self.texture.width() as f32;
+        let tex_h = self.texture.height() as f32;
+        if w <= 0. || h <= 0. || tex_w <= 0. || tex_h <= 0. {
+            return RenderData::default();
+        }
+
+        // Size of the whole image once scaled; it is centred in the box.
+        let (img_w, img_h) = match self.fit {
+            ImageFit::Contain => {
+                let scale = (w / tex_w).min(h / tex_h);
+                (tex_w * scale, tex_h * scale)
+            },
+            ImageFit::Cover => {
+                let scale = (w / tex_w).max(h / tex_h);
+                (tex_w * scale, tex_h * scale)
+            },
+            ImageFit::Fill => (w, h),
+            ImageFit::None => (tex_w, tex_h),
+        };
+        let img_left = left + (w - img_w) / 2.;
+        let img_top = top + (h - img_h) / 2.;
+
+        // Crop to the box, and pick out the matching part of the texture.
+        let x0 = img_left.max(left);
+        let y0 = img_top.max(top);
+        let x1 = (img_left + img_w).min(left + w);
+        let y1 = (img_top + img_h).min(top + h);
+        let uv_min = ((x0 - img_left) / img_w, (y0 - img_top) / img_h);
+        let uv_max = ((x1 - img_left) / img_w, (y1 - img_top) / img_h);
+
+        let quad = Quad {
+            top_left: Coord { x: x0.round() as i16, y: y0.round() as i16 },
+            width: (x1 - x0).round() as u16,
+            height: (y1 - y0).round() as u16,
+            colour: self.tint,
+        };
+        quad.render_textured(self.texture.clone(), uv_min, uv_max)
+    }
+}
+
+/// Vector artwork, scaled to fit its layout box while keeping its aspect ratio.
+pub struct Icon {
+    svg: Arc<Svg>,
+    /// If set, replaces every colour in the artwork, e.g. to match the text around it.
+    pub colour: Option<Colour>,
+}
+
+impl Icon {
+    pub fn new(svg: Arc<Svg>) -> Self {
+        Self { svg, colour: None }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Svg::load(path)?))
+    }
+
+    pub fn colour(mut self, col: Colour) -> Self {
+        self.colour = Some(col);
+        self
+    }
+
+    pub fn svg(&self) -> &Arc<Svg> {
+        &self.svg
+    }
+
+    fn intrinsic_size(&self) -> (f32, f32) {
+        self.svg.size()
+    }
+
+    fn render(&self, left: f32, top: f32, w: f32, h: f32) -> RenderData {
+        self.svg.render(&Rect::new(left, top, w, h), self.colour)
+    }
+}
+
+impl From<Icon> for Component {
+    fn from(icon: Icon) -> Self {
+        Component::new(Content::Icon(icon))
+    }
+}
+
+impl From<Icon> for GuiObject {
+    fn from(icon: Icon) -> Self {
+        GuiObject::Component(icon.into())
+    }
+}
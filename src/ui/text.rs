@@ -0,0 +1,547 @@
+use std::cell::RefCell;
+
+use crate::graphics::{Colour, RenderData};
+use crate::graphics::export::Text;
+use crate::graphics::shaping::{FontStack, ShapedLine};
+use crate::ui::{Component, Content, GuiObject};
+
+/// A single line of text.
+pub struct Label {
+    fonts: FontStack,
+    pub text: String,
+    pub size: f32,
+    pub colour: Colour,
+}
+
+impl Label {
+    pub fn new<F: Into<FontStack>>(fonts: F, text: &str) -> Self {
+        Self {
+            fonts: fonts.into(),
+            text: text.to_string(),
+            size: 16.,
+            colour: Colour::black(),
+        }
+    }
+
+    pub fn size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn colour(mut self, col: Colour) -> Self {
+        self.colour = col;
+        self
+    }
+
+    pub fn fonts(&self) -> &FontStack {
+        &self.fonts
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+    }
+
+    pub(crate) fn intrinsic_size(&self) -> (f32, f32) {
+        (self.fonts.measure(&self.text, self.size), self.fonts.metrics(self.size).line_height())
+    }
+
+    pub(crate) fn render(&self, left: f32, top: f32, w: f32, h: f32) -> RenderData {
+        let text = self.export(left, top, w, h);
+        self.fonts.render(&text.line, self.size, self.colour)
+    }
+
+    pub(crate) fn export(&self, left: f32, top: f32, _w: f32, _h: f32) -> Text {
+        let baseline = top + self.fonts.metrics(self.size).ascent;
+        Text {
+            text: self.text.clone(),
+            fonts: self.fonts.clone(),
+            size: self.size,
+            colour: self.colour,
+            x: left,
+            y: baseline,
+            word_spacing: 0.,
+            line: self.fonts.shape_line(&self.text, self.size, left, baseline, 0.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+    /// Stretch the gaps between words so that every line but the last fills the width.
+    Justify,
+}
+
+/// Text wrapped at word boundaries to the width of its layout box.
+pub struct Paragraph {
+    fonts: FontStack,
+    pub text: String,
+    pub size: f32,
+    pub colour: Colour,
+    pub align: TextAlign,
+    /// Multiplier applied to the font's natural line height.
+    pub line_height: f32,
+    pub max_lines: Option<usize>,
+    /// Whether to end the last line with an ellipsis when `max_lines` cuts the text short.
+    pub ellipsis: bool,
+    /// The most recent wrappings, newest first.
+    wrapped: RefCell<Vec<Wrapped>>,
+}
+
+/// How many wrappings a paragraph keeps. Layout measures at the width on offer and then draws at
+/// the width it gives, which can differ, so it takes two to stop either pushing out the other.
+const WRAPPINGS: usize = 2;
+
+/// The text wrapped at one width, kept until anything it depends on changes so that the text
+/// isn't wrapped and shaped again whenever it is measured or drawn.
+struct Wrapped {
+    text: String,
+    size: f32,
+    width: f32,
+    max_lines: Option<usize>,
+    ellipsis: bool,
+    align: TextAlign,
+    lines: Vec<Line>,
+    /// Filled in when the paragraph is first drawn at this width.
+    shaped: Option<Vec<AlignedLine>>,
+}
+
+/// A wrapped line, shaped and aligned relative to the left of its box and a baseline of 0.
+#[derive(Clone)]
+struct AlignedLine {
+    text: String,
+    x: f32,
+    word_spacing: f32,
+    line: ShapedLine,
+}
+
+/// A line of wrapped text, with the width of each of its words.
+struct Line {
+    words: Vec<(String, f32)>,
+    /// Whether the line ends a paragraph, in which case it isn't stretched when justifying.
+    last: bool,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self { words: Vec::new(), last: false }
+    }
+
+    fn width(&self, space: f32) -> f32 {
+        let words: f32 = self.words.iter().map(|(_, w)| w).sum();
+        words + space * self.words.len().saturating_sub(1) as f32
+    }
+}
+
+/// The byte offset and text of each run of non-whitespace in `text`.
+fn word_indices(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (true, Some(word_start)) => {
+                words.push((word_start, &text[word_start..i]));
+                start = None;
+            },
+            (false, None) => start = Some(i),
+            _ => {},
+        }
+    }
+    words
+}
+
+/// The widths of the grapheme clusters of some text, summed up as they go, to measure any range
+/// of it in constant time.
+struct Advances {
+    /// The byte offset of each cluster.
+    offsets: Vec<usize>,
+    /// `sums[i]` is the width of the clusters before the `i`th.
+    sums: Vec<f32>,
+}
+
+impl Advances {
+    fn new(advances: Vec<(usize, f32)>) -> Self {
+        let mut sums = Vec::with_capacity(advances.len() + 1);
+        let mut sum = 0.;
+        sums.push(sum);
+        let offsets = advances.into_iter().map(|(offset, advance)| {
+            sum += advance;
+            sums.push(sum);
+            offset
+        }).collect();
+        Self { offsets, sums }
+    }
+
+    /// The index of the first cluster starting at or after byte `offset`.
+    fn index(&self, offset: usize) -> usize {
+        self.offsets.partition_point(|start| *start < offset)
+    }
+
+    /// The width of the text between byte offsets `start` and `end`.
+    fn width(&self, start: usize, end: usize) -> f32 {
+        self.sums[self.index(end)] - self.sums[self.index(start)]
+    }
+}
+
+impl Paragraph {
+    pub fn new<F: Into<FontStack>>(fonts: F, text: &str) -> Self {
+        Self {
+            fonts: fonts.into(),
+            text: text.to_string(),
+            size: 16.,
+            colour: Colour::black(),
+            align: TextAlign::Left,
+            line_height: 1.,
+            max_lines: None,
+            ellipsis: false,
+            wrapped: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn colour(mut self, col: Colour) -> Self {
+        self.colour = col;
+        self
+    }
+
+    pub fn align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn line_height(mut self, line_height: f32) -> Self {
+        self.line_height = line_height;
+        self
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    pub fn ellipsis(mut self, ellipsis: bool) -> Self {
+        self.ellipsis = ellipsis;
+        self
+    }
+
+    pub fn fonts(&self) -> &FontStack {
+        &self.fonts
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+    }
+
+    fn measure(&self, text: &str) -> f32 {
+        self.fonts.measure(text, self.size)
+    }
+
+    /// Splits a word that is too wide for a line on its own into pieces that fit, keeping at
+    /// least one grapheme cluster in each. The word starts at byte `start` of the measured text.
+    fn break_word(word: &str, start: usize, advances: &Advances, max_width: f32) -> Vec<(String, f32)> {
+        let mut pieces = Vec::new();
+        let mut piece_start = 0;
+        let mut width = 0.;
+        for i in advances.index(start)..advances.index(start + word.len()) {
+            let offset = advances.offsets[i] - start;
+            let advance = advances.sums[i + 1] - advances.sums[i];
+            if offset > piece_start && width + advance > max_width {
+                pieces.push((word[piece_start..offset].to_string(), width));
+                piece_start = offset;
+                width = 0.;
+            }
+            width += advance;
+        }
+        pieces.push((word[piece_start..].to_string(), width));
+        pieces
+    }
+
+    /// Greedily wraps the text into lines no wider than `max_width`, then applies `max_lines`.
+    ///
+    /// Each paragraph is shaped once, and words are measured from its advances.
+    fn lines(&self, max_width: f32) -> Vec<Line> {
+        let space = self.measure(" ");
+        let mut lines = Vec::new();
+
+        for paragraph in self.text.split('\n') {
+            let advances = Advances::new(self.fonts.cluster_advances(paragraph, self.size));
+            let mut line = Line::new();
+            let mut line_width = 0.;
+            for (start, word) in word_indices(paragraph) {
+                let width = advances.width(start, start + word.len());
+                if !line.words.is_empty() && line_width + space + width <= max_width {
+                    line.words.push((word.to_string(), width));
+                    line_width += space + width;
+                    continue;
+                }
+                if !line.words.is_empty() {
+                    lines.push(std::mem::replace(&mut line, Line::new()));
+                }
+                if width <= max_width {
+                    line.words.push((word.to_string(), width));
+                    line_width = width;
+                } else {
+                    let mut pieces = Self::break_word(word, start, &advances, max_width);
+                    let rest = pieces.pop();
+                    lines.extend(pieces.into_iter().map(|piece| Line { words: vec![piece], last: false }));
+                    line_width = rest.as_ref().map_or(0., |(_, width)| *width);
+                    line.words.extend(rest);
+                }
+            }
+            line.last = true;
+            lines.push(line);
+        }
+
+        if let Some(max_lines) = self.max_lines {
+            if lines.len() > max_lines {
+                lines.truncate(max_lines);
+                if let Some(line) = lines.last_mut() {
+                    line.last = true;
+                    if self.ellipsis {
+                        self.ellipsize(line, max_width);
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// Replaces the end of `line` with an ellipsis, dropping grapheme clusters until it fits.
+    fn ellipsize(&self, line: &mut Line, max_width: f32) {
+        let ellipsis = if self.fonts.primary().has_glyph('…') { "…" } else { "..." };
+        let ellipsis_width = self.measure(ellipsis);
+        let text = line.words.iter().map(|(word, _)| word.as_str()).collect::<Vec<_>>().join(" ");
+        let advances = Advances::new(self.fonts.cluster_advances(&text, self.size));
+        let ends = advances.offsets.iter().copied().chain(std::iter::once(text.len())).rev();
+        for end in ends {
+            let kept = text[..end].trim_end();
+            let width = advances.width(0, kept.len()) + ellipsis_width;
+            if width <= max_width || kept.is_empty() {
+                line.words = vec![(format!("{}{}", kept, ellipsis), width)];
+                return;
+            }
+        }
+    }
+
+    /// Runs `f` on the text wrapped at `max_width`, wrapping it first unless a recent wrapping
+    /// still holds.
+    fn with_wrapped<T>(&self, max_width: f32, f: impl FnOnce(&mut Wrapped) -> T) -> T {
+        let mut wrapped = self.wrapped.borrow_mut();
+        let current = wrapped.iter().position(|wrapped| {
+            wrapped.text == self.text && wrapped.size == self.size && wrapped.width == max_width
+                && wrapped.max_lines == self.max_lines && wrapped.ellipsis == self.ellipsis
+                && wrapped.align == self.align
+        });
+        match current {
+            Some(i) => {
+                let current = wrapped.remove(i);
+                wrapped.insert(0, current);
+            },
+            None => {
+                wrapped.insert(0, Wrapped {
+                    text: self.text.clone(),
+                    size: self.size,
+                    width: max_width,
+                    max_lines: self.max_lines,
+                    ellipsis: self.ellipsis,
+                    align: self.align,
+                    lines: self.lines(max_width),
+                    shaped: None,
+                });
+                wrapped.truncate(WRAPPINGS);
+            },
+        }
+        f(&mut wrapped[0])
+    }
+
+    /// Shapes and aligns each of `lines` in a box `w` wide.
+    fn align_lines(&self, lines: &[Line], w: f32) -> Vec<AlignedLine> {
+        lines.iter().map(|line| {
+            // Each line is shaped as a whole so that bidi reordering applies across its words.
+            let text = line.words.iter().map(|(word, _)| word.as_str()).collect::<Vec<_>>().join(" ");
+            let shaped = self.fonts.shape_line(&text, self.size, 0., 0., 0.);
+            let (x, word_spacing) = match self.align {
+                TextAlign::Left => (0., 0.),
+                TextAlign::Center => ((w - shaped.width) / 2., 0.),
+                TextAlign::Right => (w - shaped.width, 0.),
+                TextAlign::Justify if !line.last && line.words.len() > 1 => {
+                    (0., (w - shaped.width) / (line.words.len() - 1) as f32)
+                },
+                TextAlign::Justify => (0., 0.),
+            };
+            let line = if word_spacing > 0. {
+                self.fonts.shape_line(&text, self.size, x, 0., word_spacing)
+            } else {
+                shaped.offset(x)
+            };
+            AlignedLine { text, x, word_spacing, line }
+        }).collect()
+    }
+
+    fn line_advance(&self) -> f32 {
+        self.fonts.metrics(self.size).line_height() * self.line_height
+    }
+
+    pub(crate) fn intrinsic_size(&self, max_width: f32) -> (f32, f32) {
+        let space = self.measure(" ");
+        let (width, lines) = self.with_wrapped(max_width, |wrapped| {
+            let width = wrapped.lines.iter().map(|line| line.width(space)).fold(0., f32::max);
+            (width, wrapped.lines.len())
+        });
+        (width, lines as f32 * self.line_advance())
+    }
+
+    pub(crate) fn render(&self, left: f32, top: f32, w: f32, h: f32) -> RenderData {
+        let mut data = RenderData::default();
+        for text in self.export(left, top, w, h) {
+            data.append(self.fonts.render(&text.line, self.size, self.colour));
+        }
+        data
+    }
+
+    /// Wraps and aligns the text, giving each line as it is drawn.
+    pub(crate) fn export(&self, left: f32, top: f32, w: f32, _h: f32) -> Vec<Text> {
+        let metrics = self.fonts.metrics(self.size);
+        let advance = self.line_advance();
+        // Extra line height is shared equally above and below the text.
+        let mut baseline = top + (advance - metrics.line_height()) / 2. + metrics.ascent;
+
+        let lines = self.with_wrapped(w, |wrapped| {
+            let lines = &wrapped.lines;
+            wrapped.shaped.get_or_insert_with(|| self.align_lines(lines, w)).clone()
+        });
+        let mut texts = Vec::new();
+        for line in lines {
+            texts.push(Text {
+                text: line.text,
+                fonts: self.fonts.clone(),
+                size: self.size,
+                colour: self.colour,
+                x: left + line.x,
+                y: baseline,
+                word_spacing: line.word_spacing,
+                line: line.line.translate(left, baseline),
+            });
+            baseline += advance;
+        }
+        texts
+    }
+}
+
+impl From<Label> for Component {
+    fn from(label: Label) -> Self {
+        Component::new(Content::Label(label))
+    }
+}
+
+impl From<Label> for GuiObject {
+    fn from(label: Label) -> Self {
+        GuiObject::Component(label.into())
+    }
+}
+
+impl From<Paragraph> for Component {
+    fn from(paragraph: Paragraph) -> Self {
+        Component::new(Content::Paragraph(paragraph))
+    }
+}
+
+impl From<Paragraph> for GuiObject {
+    fn from(paragraph: Paragraph) -> Self {
+        GuiObject::Component(paragraph.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::font::Font;
+
+    const SIZE: f32 = 20.;
+
+    fn paragraph(text: &str) -> Paragraph {
+        let font = Font::from_bytes(include_bytes!("../../tests/fonts/DejaVuSansMono.ttf").to_vec()).unwrap();
+        Paragraph::new(font, text).size(SIZE)
+    }
+
+    /// Every character in the test font is this wide.
+    fn char_width(paragraph: &Paragraph) -> f32 {
+        paragraph.measure("x")
+    }
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines.iter()
+            .map(|line| line.words.iter().map(|(word, _)| word.as_str()).collect::<Vec<_>>().join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn measures_glyphs_as_they_are_drawn() {
+        let paragraph = paragraph("x");
+        let font = paragraph.fonts.primary();
+        assert!((char_width(&paragraph) - font.advance(font.glyph_id('x'), SIZE)).abs() < 0.01);
+    }
+
+    #[test]
+    fn wraps_at_word_boundaries() {
+        let paragraph = paragraph("aaa bbb  ccc\ndd");
+        let lines = paragraph.lines(char_width(&paragraph) * 7.5);
+        assert_eq!(texts(&lines), ["aaa bbb", "ccc", "dd"]);
+        assert_eq!(lines.iter().map(|line| line.last).collect::<Vec<_>>(), [false, true, true]);
+        assert!((lines[0].words[1].1 - char_width(&paragraph) * 3.).abs() < 0.01);
+    }
+
+    #[test]
+    fn breaks_words_wider_than_a_line() {
+        let paragraph = paragraph("ab abcdefghij");
+        let width = char_width(&paragraph);
+        let lines = paragraph.lines(width * 4.5);
+        assert_eq!(texts(&lines), ["ab", "abcd", "efgh", "ij"]);
+        assert!((lines[3].width(0.) - width * 2.).abs() < 0.01);
+    }
+
+    #[test]
+    fn ellipsizes_the_last_line() {
+        let paragraph = paragraph("aaa bbb ccc").max_lines(1).ellipsis(true);
+        let lines = paragraph.lines(char_width(&paragraph) * 7.5);
+        assert_eq!(texts(&lines), ["aaa bb…"]);
+        assert!(lines[0].last);
+    }
+
+    #[test]
+    fn justifies_every_line_but_the_last() {
+        let paragraph = paragraph("aa bb cc dd").align(TextAlign::Justify);
+        let width = char_width(&paragraph) * 8.5;
+        let texts = paragraph.export(10., 0., width, 100.);
+
+        assert_eq!(texts.len(), 2);
+        assert!((texts[0].word_spacing - char_width(&paragraph) * 0.25).abs() < 0.01);
+        assert!((texts[0].line.width - width).abs() < 0.01);
+        assert_eq!(texts[1].text, "dd");
+        assert_eq!(texts[1].word_spacing, 0.);
+        assert!((texts[1].line.glyphs[0].glyph.x - 10.).abs() < 0.01);
+    }
+
+    #[test]
+    fn keeps_wrapping_until_the_text_changes() {
+        let mut paragraph = paragraph("aa bb cc");
+        let width = char_width(&paragraph) * 5.5;
+        let first = paragraph.export(0., 0., width, 100.);
+        let moved = paragraph.export(10., 20., width, 100.);
+        assert_eq!(paragraph.wrapped.borrow().len(), 1);
+        assert!((moved[1].line.glyphs[0].glyph.x - first[1].line.glyphs[0].glyph.x - 10.).abs() < 0.01);
+        assert!((moved[1].y - first[1].y - 20.).abs() < 0.01);
+
+        paragraph.set_text("aa bb cc dd ee");
+        assert_eq!(paragraph.export(0., 0., width, 100.).len(), 3);
+        paragraph.intrinsic_size(width * 2.);
+        paragraph.intrinsic_size(width * 3.);
+        assert_eq!(paragraph.wrapped.borrow().len(), WRAPPINGS);
+    }
+}